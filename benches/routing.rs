@@ -0,0 +1,70 @@
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use dnx_rs::{
+    rules::{DnxPattern, DnxRules},
+    tree::{Tree, TreeSortable},
+};
+
+struct Zone(String);
+
+impl TreeSortable<String> for Zone {
+    fn get_path(&self) -> Vec<String> {
+        self.0.get_path()
+    }
+}
+
+fn zone_tree(count: usize) -> Tree<String, Zone> {
+    let mut tree = Tree::new();
+    for i in 0..count {
+        tree.insert(Zone(format!("customer{i}.example.com.")));
+    }
+    tree
+}
+
+fn glob_rules(count: usize) -> DnxRules<usize> {
+    let mut rules = DnxRules::new();
+    for i in 0..count {
+        rules.push(&DnxPattern::Glob(format!("*-dc{i:02}.customer{i}.local.")), i).unwrap();
+    }
+    rules
+}
+
+fn regex_rules(count: usize) -> DnxRules<usize> {
+    let mut rules = DnxRules::new();
+    for i in 0..count {
+        rules.push(&DnxPattern::Regex(format!(r"host[0-9]+\.lab{i}\.")), i).unwrap();
+    }
+    rules
+}
+
+/// Compares the zone tree with pattern rules. Rules are evaluated in order
+/// before the tree, so a miss costs one regex match per rule.
+fn bench_routing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("routing");
+
+    for count in [10, 100, 1000] {
+        let tree = zone_tree(count);
+        group.bench_with_input(BenchmarkId::new("tree_find", count), &count, |b, _| {
+            b.iter(|| tree.find(black_box("host.customer5.example.com.")))
+        });
+
+        let globs = glob_rules(count);
+        group.bench_with_input(BenchmarkId::new("glob_rules_hit_first", count), &count, |b, _| {
+            b.iter(|| globs.find(black_box("corp-dc00.customer0.local.")))
+        });
+        group.bench_with_input(BenchmarkId::new("glob_rules_miss", count), &count, |b, _| {
+            b.iter(|| globs.find(black_box("host.customer5.example.com.")))
+        });
+
+        let regexes = regex_rules(count);
+        group.bench_with_input(BenchmarkId::new("regex_rules_miss", count), &count, |b, _| {
+            b.iter(|| regexes.find(black_box("host.customer5.example.com.")))
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_routing);
+criterion_main!(benches);
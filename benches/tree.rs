@@ -0,0 +1,58 @@
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use dnx_rs::tree::{Label, Tree, TreeSortable};
+use hickory_server::proto::rr::{LowerName, Name};
+
+struct Zone(String);
+
+impl TreeSortable<String> for Zone {
+    fn get_path(&self) -> Vec<String> {
+        self.0.get_path()
+    }
+}
+
+impl TreeSortable<Label> for Zone {
+    fn get_path(&self) -> Vec<Label> {
+        self.0.get_path()
+    }
+}
+
+fn zones(count: usize) -> impl Iterator<Item = Zone> {
+    (0..count).map(|i| Zone(format!("site{i}.customer{}.example.com.", i % 10)))
+}
+
+/// Compares the string-keyed tree, which renders and splits every query
+/// name, with the label-keyed tree walking the name's labels in place.
+fn bench_tree_find(c: &mut Criterion) {
+    let mut group = c.benchmark_group("tree_find");
+    let name = LowerName::from(Name::from_ascii("host.dept.site5.customer5.example.com.").unwrap());
+    let miss = LowerName::from(Name::from_ascii("www.example.org.").unwrap());
+
+    for count in [10, 1000] {
+        let mut strings: Tree<String, Zone> = Tree::new();
+        let mut labels: Tree<Label, Zone> = Tree::new();
+        for zone in zones(count) {
+            strings.insert(Zone(zone.0.clone()));
+            labels.insert(zone);
+        }
+
+        group.bench_with_input(BenchmarkId::new("string_hit", count), &count, |b, _| {
+            b.iter(|| strings.find(black_box(&name)).is_some())
+        });
+        group.bench_with_input(BenchmarkId::new("label_hit", count), &count, |b, _| {
+            b.iter(|| labels.find_name(black_box(&name)).is_some())
+        });
+        group.bench_with_input(BenchmarkId::new("string_miss", count), &count, |b, _| {
+            b.iter(|| strings.find(black_box(&miss)).is_some())
+        });
+        group.bench_with_input(BenchmarkId::new("label_miss", count), &count, |b, _| {
+            b.iter(|| labels.find_name(black_box(&miss)).is_some())
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_tree_find);
+criterion_main!(benches);
@@ -0,0 +1,84 @@
+use std::net::IpAddr;
+
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+
+/// Client access control list. A client is refused if it matches any `deny`
+/// network, or if `allow` is non-empty and it matches none of its networks.
+/// Lists left out are `None`, unlike empty ones, so zones can tell an ACL
+/// to inherit from one that was set to let everyone in.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct DnxAcl {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allow: Option<Vec<IpNet>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deny: Option<Vec<IpNet>>,
+}
+
+impl DnxAcl {
+    /// Whether both lists were left out.
+    pub fn is_unset(&self) -> bool {
+        self.allow.is_none() && self.deny.is_none()
+    }
+
+    pub fn permits(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+
+        if self.deny.iter().flatten().any(|net| net.contains(&ip)) {
+            return false;
+        }
+
+        match self.allow {
+            Some(ref allow) if !allow.is_empty() => allow.iter().any(|net| net.contains(&ip)),
+            _ => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn acl(allow: &[&str], deny: &[&str]) -> DnxAcl {
+        DnxAcl {
+            allow: Some(allow.iter().map(|net| net.parse().unwrap()).collect()),
+            deny: Some(deny.iter().map(|net| net.parse().unwrap()).collect()),
+        }
+    }
+
+    #[test]
+    fn test_empty_acl_permits_everyone() {
+        for acl in [DnxAcl::default(), acl(&[], &[])] {
+            assert!(acl.permits("192.168.1.1".parse().unwrap()));
+            assert!(acl.permits("2001:db8::1".parse().unwrap()));
+        }
+        assert!(DnxAcl::default().is_unset());
+        assert!(!acl(&[], &[]).is_unset());
+    }
+
+    #[test]
+    fn test_allow_list() {
+        let acl = acl(&["10.0.0.0/8", "192.168.1.0/24"], &[]);
+
+        assert!(acl.permits("10.1.2.3".parse().unwrap()));
+        assert!(acl.permits("192.168.1.20".parse().unwrap()));
+        assert!(!acl.permits("192.168.2.20".parse().unwrap()));
+        assert!(!acl.permits("8.8.8.8".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_deny_takes_precedence() {
+        let acl = acl(&["10.0.0.0/8"], &["10.0.5.0/24"]);
+
+        assert!(acl.permits("10.0.4.1".parse().unwrap()));
+        assert!(!acl.permits("10.0.5.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_ipv4_mapped_addresses() {
+        let acl = acl(&["10.0.0.0/8"], &[]);
+
+        assert!(acl.permits("::ffff:10.0.0.1".parse().unwrap()));
+    }
+}
@@ -0,0 +1,252 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
+};
+
+use hickory_server::{
+    authority::{Authority, LookupOptions, ZoneType},
+    proto::{
+        op::ResponseCode,
+        rr::{LowerName, Name, Record},
+    },
+    server::RequestInfo,
+    store::file::{FileAuthority, FileConfig},
+};
+
+use crate::watch::DnxFileWatch;
+
+/// The sections of an authoritative answer from a zone file.
+#[derive(Debug, Default)]
+pub struct DnxAuthorityAnswer {
+    pub response_code: ResponseCode,
+    pub answers: Vec<Record>,
+    pub name_servers: Vec<Record>,
+    pub soa: Vec<Record>,
+    pub additionals: Vec<Record>,
+}
+
+struct LoadedZone {
+    origin: Name,
+    watch: Mutex<DnxFileWatch>,
+    authority: RwLock<Option<Arc<FileAuthority>>>,
+}
+
+impl LoadedZone {
+    fn load(origin: &Name, path: &Path) -> Result<FileAuthority, String> {
+        let config = FileConfig {
+            zone_file_path: path.to_string_lossy().into_owned(),
+        };
+
+        FileAuthority::try_from_config(origin.clone(), ZoneType::Primary, false, None, &config)
+    }
+
+    /// Replaces the zone with the file's contents. A zone that fails to load
+    /// keeps serving its last good version.
+    fn reload(&self) {
+        let watch = self.watch.lock().unwrap();
+        match LoadedZone::load(&self.origin, watch.path()) {
+            Ok(authority) => {
+                log::info!("Loaded zone {} from {}", self.origin, watch.path().display());
+                *self.authority.write().unwrap() = Some(Arc::new(authority));
+            }
+            Err(e) => {
+                log::error!("Failed to load zone {} from {}: {}", self.origin, watch.path().display(), e);
+            }
+        }
+    }
+}
+
+/// Zones served authoritatively from RFC 1035 zone files.
+#[derive(Default)]
+pub struct DnxAuthorities {
+    zones: HashMap<LowerName, LoadedZone>,
+}
+
+impl DnxAuthorities {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads the zone `origin` from `path`.
+    pub fn insert(&mut self, origin: Name, path: PathBuf) {
+        let zone = LoadedZone {
+            origin: origin.clone(),
+            watch: Mutex::new(DnxFileWatch::new(path)),
+            authority: RwLock::new(None),
+        };
+        zone.reload();
+        self.zones.insert(LowerName::new(&origin), zone);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.zones.is_empty()
+    }
+
+    pub fn contains(&self, origin: &LowerName) -> bool {
+        self.zones.contains_key(origin)
+    }
+
+    /// Answers a query from the zone `origin`. Names without records are
+    /// answered NXDOMAIN and types without records NODATA, both with the
+    /// zone's SOA. Returns `None` if the zone isn't loaded.
+    pub async fn lookup(&self, origin: &LowerName, request_info: RequestInfo<'_>) -> Option<DnxAuthorityAnswer> {
+        let authority = self.zones.get(origin)?.authority.read().unwrap().clone()?;
+        let options = LookupOptions::default();
+        let query_type = request_info.query.query_type();
+
+        let mut answer = DnxAuthorityAnswer::default();
+        match authority.search(request_info, options).await {
+            Ok(mut answers) => {
+                if let Some(additionals) = answers.take_additionals() {
+                    answer.additionals = additionals.iter().cloned().collect();
+                }
+                answer.answers = answers.iter().cloned().collect();
+
+                // SOA queries get the zone's name servers as well
+                if query_type.is_soa() {
+                    match authority.ns(options).await {
+                        Ok(ns) => answer.name_servers = ns.iter().cloned().collect(),
+                        Err(e) => log::warn!("Failed to look up NS records of {}: {}", origin, e),
+                    }
+                }
+            }
+            Err(e) if e.is_refused() => {
+                answer.response_code = ResponseCode::Refused;
+            }
+            Err(e) => {
+                if e.is_nx_domain() {
+                    answer.response_code = ResponseCode::NXDomain;
+                }
+                match authority.soa_secure(options).await {
+                    Ok(soa) => answer.soa = soa.iter().cloned().collect(),
+                    Err(e) => log::warn!("Failed to look up SOA record of {}: {}", origin, e),
+                }
+            }
+        }
+
+        Some(answer)
+    }
+
+    /// Reloads any zone whose file changed since it was last loaded.
+    pub fn reload_changed(&self) {
+        for zone in self.zones.values() {
+            let changed = zone.watch.lock().unwrap().changed();
+            if changed {
+                zone.reload();
+            }
+        }
+    }
+}
+
+/// Polls the zone files every `interval`, reloading those that changed.
+pub fn spawn_reloader(authorities: Arc<DnxAuthorities>, interval: Duration) {
+    if authorities.is_empty() {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.tick().await;
+
+        loop {
+            ticker.tick().await;
+            let authorities = authorities.clone();
+            let _ = tokio::task::spawn_blocking(move || authorities.reload_changed()).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, str::FromStr};
+
+    use hickory_server::{
+        proto::{
+            op::{Header, LowerQuery, Query},
+            rr::{RData, RecordType},
+        },
+        server::Protocol,
+    };
+
+    use super::*;
+
+    const ZONE: &str = "\
+$ORIGIN lab.example.
+$TTL 300
+@       IN SOA  ns1 hostmaster 1 3600 600 86400 60
+@       IN NS   ns1
+ns1     IN A    10.0.0.1
+www     IN A    10.0.0.10
+*.apps  IN A    10.0.0.20
+";
+
+    fn authorities(name: &str, zone: &str) -> (DnxAuthorities, PathBuf) {
+        let path = std::env::temp_dir().join(format!("dnx-zone-{}-{}", name, std::process::id()));
+        fs::write(&path, zone).unwrap();
+
+        let mut authorities = DnxAuthorities::new();
+        authorities.insert(Name::from_str("lab.example.").unwrap(), path.clone());
+        (authorities, path)
+    }
+
+    async fn lookup(authorities: &DnxAuthorities, name: &str, query_type: RecordType) -> DnxAuthorityAnswer {
+        let header = Header::new();
+        let query = LowerQuery::from(Query::query(Name::from_str(name).unwrap(), query_type));
+        let request_info = RequestInfo::new("127.0.0.1:53".parse().unwrap(), Protocol::Udp, &header, &query);
+        let origin = LowerName::from(Name::from_str("lab.example.").unwrap());
+
+        authorities.lookup(&origin, request_info).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_zone_file_answers() {
+        let (authorities, path) = authorities("answers", ZONE);
+
+        let answer = lookup(&authorities, "www.lab.example.", RecordType::A).await;
+        assert_eq!(answer.response_code, ResponseCode::NoError);
+        assert_eq!(answer.answers[0].data(), Some(&RData::A("10.0.0.10".parse::<std::net::Ipv4Addr>().unwrap().into())));
+
+        let answer = lookup(&authorities, "x.apps.lab.example.", RecordType::A).await;
+        assert_eq!(answer.answers.len(), 1);
+
+        let answer = lookup(&authorities, "lab.example.", RecordType::SOA).await;
+        assert_eq!(answer.answers[0].record_type(), RecordType::SOA);
+        assert_eq!(answer.name_servers[0].record_type(), RecordType::NS);
+
+        let answer = lookup(&authorities, "missing.lab.example.", RecordType::A).await;
+        assert_eq!(answer.response_code, ResponseCode::NXDomain);
+        assert_eq!(answer.soa[0].record_type(), RecordType::SOA);
+
+        let answer = lookup(&authorities, "www.lab.example.", RecordType::AAAA).await;
+        assert_eq!(answer.response_code, ResponseCode::NoError);
+        assert!(answer.answers.is_empty());
+        assert_eq!(answer.soa.len(), 1);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_zone_file_reload_keeps_last_good_zone() {
+        let (authorities, path) = authorities("reload", ZONE);
+
+        fs::write(&path, ZONE.replace("10.0.0.10", "10.0.0.11")).unwrap();
+        let file = fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(std::time::SystemTime::now() + Duration::from_secs(60)).unwrap();
+        authorities.reload_changed();
+
+        let answer = lookup(&authorities, "www.lab.example.", RecordType::A).await;
+        assert_eq!(answer.answers[0].data(), Some(&RData::A("10.0.0.11".parse::<std::net::Ipv4Addr>().unwrap().into())));
+
+        fs::write(&path, "not a zone").unwrap();
+        let file = fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(std::time::SystemTime::now() + Duration::from_secs(120)).unwrap();
+        authorities.reload_changed();
+
+        let answer = lookup(&authorities, "www.lab.example.", RecordType::A).await;
+        assert_eq!(answer.answers.len(), 1);
+
+        fs::remove_file(path).unwrap();
+    }
+}
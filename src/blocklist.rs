@@ -0,0 +1,439 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io,
+    net::IpAddr,
+    path::PathBuf,
+    str::FromStr,
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
+};
+
+use hickory_server::proto::{
+    op::ResponseCode,
+    rr::{LowerName, Name, RData, Record, RecordType},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::watch::DnxFileWatch;
+
+const SINKHOLE_TTL: u32 = 60;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DnxBlocklistFormat {
+    /// One domain per line.
+    Domains,
+    /// /etc/hosts format; the addresses are ignored.
+    Hosts,
+    /// An RPZ zone file, where each entry carries its own action.
+    Rpz,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DnxBlockAction {
+    /// Answer NXDOMAIN.
+    Nxdomain,
+    /// Answer NOERROR with no records.
+    Nodata,
+    /// Answer with this address for queries of its family, NODATA otherwise.
+    Sinkhole(IpAddr),
+    /// Forward the query as usual, overriding later lists.
+    Passthrough,
+}
+
+impl DnxBlockAction {
+    /// The response code and answers for a query blocked by this action.
+    pub fn answer(&self, name: &LowerName, query_type: RecordType) -> (ResponseCode, Vec<Record>) {
+        let rdata = match (self, query_type) {
+            (DnxBlockAction::Nxdomain, _) => return (ResponseCode::NXDomain, Vec::new()),
+            (DnxBlockAction::Sinkhole(IpAddr::V4(ip)), RecordType::A | RecordType::ANY) => RData::A((*ip).into()),
+            (DnxBlockAction::Sinkhole(IpAddr::V6(ip)), RecordType::AAAA | RecordType::ANY) => RData::AAAA((*ip).into()),
+            _ => return (ResponseCode::NoError, Vec::new()),
+        };
+
+        let record = Record::from_rdata(name.into(), SINKHOLE_TTL, rdata);
+        (ResponseCode::NoError, vec![record])
+    }
+}
+
+fn default_subdomains() -> bool {
+    true
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DnxBlocklist {
+    pub path: PathBuf,
+    pub format: DnxBlocklistFormat,
+    /// Action for every entry of the list. Defaults to `nxdomain` for domain
+    /// and hosts lists; for RPZ files it overrides the per-entry policies.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub action: Option<DnxBlockAction>,
+    /// Whether a plain `example.com` entry also matches its subdomains.
+    /// `*.example.com` entries only ever match subdomains.
+    #[serde(default = "default_subdomains")]
+    pub subdomains: bool,
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Entries {
+    exact: HashMap<LowerName, DnxBlockAction>,
+    wildcard: HashMap<LowerName, DnxBlockAction>,
+    len: usize,
+}
+
+impl Entries {
+    fn insert(&mut self, name: &str, action: DnxBlockAction, subdomains: bool) {
+        let (name, wildcard) = match name.strip_prefix("*.") {
+            Some(name) => (name, true),
+            None => (name, false),
+        };
+
+        let Ok(mut name) = Name::from_utf8(name) else {
+            log::warn!("Skipping invalid blocklist entry: {name}");
+            return;
+        };
+        name.set_fqdn(true);
+        let name = LowerName::from(name);
+        self.len += 1;
+
+        if !wildcard {
+            self.exact.insert(name.clone(), action);
+        }
+        if wildcard || subdomains {
+            self.wildcard.insert(name, action);
+        }
+    }
+
+    /// The most specific entry wins: an exact match, then the closest
+    /// enclosing wildcard.
+    fn find(&self, name: &LowerName) -> Option<DnxBlockAction> {
+        if let Some(action) = self.exact.get(name) {
+            return Some(*action);
+        }
+
+        let mut name = name.base_name();
+        loop {
+            if let Some(action) = self.wildcard.get(&name) {
+                return Some(*action);
+            }
+            if name.is_root() {
+                return None;
+            }
+            name = name.base_name();
+        }
+    }
+}
+
+fn parse_domains(contents: &str, action: DnxBlockAction, subdomains: bool) -> Entries {
+    let mut entries = Entries::default();
+
+    for line in contents.lines() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if !line.is_empty() {
+            entries.insert(line, action, subdomains);
+        }
+    }
+
+    entries
+}
+
+fn parse_hosts(contents: &str, action: DnxBlockAction, subdomains: bool) -> Entries {
+    let mut entries = Entries::default();
+
+    for line in contents.lines() {
+        let line = line.split('#').next().unwrap_or_default();
+        for name in line.split_whitespace().skip(1) {
+            // Hosts-format lists often include these for completeness
+            if !matches!(name, "localhost" | "localhost.localdomain" | "local" | "broadcasthost") {
+                entries.insert(name, action, subdomains);
+            }
+        }
+    }
+
+    entries
+}
+
+/// Parses the subset of RPZ used by blocklists: QNAME triggers with CNAME
+/// `.` (NXDOMAIN), CNAME `*.` (NODATA), CNAME `rpz-passthru.` or A/AAAA
+/// (sinkhole) actions. Owner names are relative to the policy zone.
+fn parse_rpz(contents: &str, action: Option<DnxBlockAction>) -> Entries {
+    let mut entries = Entries::default();
+    let mut origin = String::new();
+    let mut owner = String::new();
+
+    for line in contents.lines() {
+        let line = line.split(';').next().unwrap_or_default();
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let mut tokens: Vec<&str> = line.split_whitespace().collect();
+        if tokens[0].eq_ignore_ascii_case("$ORIGIN") {
+            if let Some(name) = tokens.get(1) {
+                origin = name.trim_end_matches('.').to_ascii_lowercase();
+            }
+            continue;
+        }
+        if tokens[0].starts_with('$') {
+            continue;
+        }
+
+        if !line.starts_with(char::is_whitespace) {
+            owner = tokens.remove(0).to_ascii_lowercase();
+        }
+
+        // Skip the optional TTL and class
+        while let Some(token) = tokens.first() {
+            if token.parse::<u32>().is_ok() || token.eq_ignore_ascii_case("IN") {
+                tokens.remove(0);
+            } else {
+                break;
+            }
+        }
+
+        let (Some(record_type), Some(data)) = (tokens.first(), tokens.get(1)) else {
+            continue;
+        };
+
+        let policy = match (record_type.to_ascii_uppercase().as_str(), *data) {
+            ("CNAME", ".") => DnxBlockAction::Nxdomain,
+            ("CNAME", "*.") => DnxBlockAction::Nodata,
+            ("CNAME", data) if data.eq_ignore_ascii_case("rpz-passthru.") => DnxBlockAction::Passthrough,
+            ("A" | "AAAA", data) => match IpAddr::from_str(data) {
+                Ok(ip) => DnxBlockAction::Sinkhole(ip),
+                Err(_) => continue,
+            },
+            _ => continue,
+        };
+
+        let name = if owner == "@" {
+            continue;
+        } else if let Some(name) = owner.strip_suffix('.') {
+            match name.strip_suffix(&origin).and_then(|name| name.strip_suffix('.')) {
+                Some(name) if !origin.is_empty() => name,
+                _ => name,
+            }
+        } else {
+            owner.as_str()
+        };
+
+        entries.insert(name, action.unwrap_or(policy), false);
+    }
+
+    entries
+}
+
+struct LoadedList {
+    config: DnxBlocklist,
+    watch: Mutex<DnxFileWatch>,
+    entries: RwLock<Arc<Entries>>,
+}
+
+impl LoadedList {
+    fn load(config: &DnxBlocklist) -> io::Result<Entries> {
+        let contents = fs::read_to_string(&config.path)?;
+        let action = config.action.unwrap_or(DnxBlockAction::Nxdomain);
+
+        Ok(match config.format {
+            DnxBlocklistFormat::Domains => parse_domains(&contents, action, config.subdomains),
+            DnxBlocklistFormat::Hosts => parse_hosts(&contents, action, config.subdomains),
+            DnxBlocklistFormat::Rpz => parse_rpz(&contents, config.action),
+        })
+    }
+
+    fn reload(&self) {
+        match LoadedList::load(&self.config) {
+            Ok(entries) => {
+                log::info!("Loaded blocklist {}: {} entries", self.config.path.display(), entries.len);
+                *self.entries.write().unwrap() = Arc::new(entries);
+            }
+            Err(e) => {
+                log::error!("Failed to load blocklist {}: {}", self.config.path.display(), e);
+            }
+        }
+    }
+}
+
+/// Blocklists consulted in order; the first list with a matching entry
+/// decides the action.
+pub struct DnxBlocklists {
+    lists: Vec<LoadedList>,
+}
+
+impl DnxBlocklists {
+    pub fn new(configs: &[DnxBlocklist]) -> Self {
+        let lists = configs.iter().map(|config| {
+            let list = LoadedList {
+                config: config.clone(),
+                watch: Mutex::new(DnxFileWatch::new(&config.path)),
+                entries: RwLock::new(Arc::new(Entries::default())),
+            };
+            list.reload();
+            list
+        }).collect();
+
+        DnxBlocklists { lists }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lists.is_empty()
+    }
+
+    pub fn find(&self, name: &LowerName) -> Option<DnxBlockAction> {
+        self.lists.iter().find_map(|list| {
+            list.entries.read().unwrap().find(name)
+        })
+    }
+
+    /// Reloads any list whose file changed since it was last loaded.
+    pub fn reload_changed(&self) {
+        for list in &self.lists {
+            if list.watch.lock().unwrap().changed() {
+                list.reload();
+            }
+        }
+    }
+}
+
+/// Polls the blocklist files every `interval`, reloading those that changed.
+pub fn spawn_reloader(blocklists: Arc<DnxBlocklists>, interval: Duration) {
+    if blocklists.is_empty() {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.tick().await;
+
+        loop {
+            ticker.tick().await;
+            let blocklists = blocklists.clone();
+            let _ = tokio::task::spawn_blocking(move || blocklists.reload_changed()).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(name: &str) -> LowerName {
+        LowerName::from(Name::from_str(name).unwrap())
+    }
+
+    #[test]
+    fn test_domains_match_subdomains() {
+        let entries = parse_domains("# ads\nads.example.com\ntracker.example.net\n", DnxBlockAction::Nxdomain, true);
+
+        assert_eq!(entries.find(&name("ads.example.com.")), Some(DnxBlockAction::Nxdomain));
+        assert_eq!(entries.find(&name("x.ADS.example.com.")), Some(DnxBlockAction::Nxdomain));
+        assert_eq!(entries.find(&name("example.com.")), None);
+        assert_eq!(entries.find(&name("notads.example.com.")), None);
+    }
+
+    #[test]
+    fn test_domains_exact_only() {
+        let entries = parse_domains("ads.example.com\n*.cdn.example.com\n", DnxBlockAction::Nodata, false);
+
+        assert_eq!(entries.find(&name("ads.example.com.")), Some(DnxBlockAction::Nodata));
+        assert_eq!(entries.find(&name("x.ads.example.com.")), None);
+        assert_eq!(entries.find(&name("cdn.example.com.")), None);
+        assert_eq!(entries.find(&name("a.b.cdn.example.com.")), Some(DnxBlockAction::Nodata));
+    }
+
+    #[test]
+    fn test_hosts_format() {
+        let entries = parse_hosts("127.0.0.1 localhost\n0.0.0.0 ads.example.com ads2.example.com # comment\n", DnxBlockAction::Nxdomain, false);
+
+        assert_eq!(entries.find(&name("ads.example.com.")), Some(DnxBlockAction::Nxdomain));
+        assert_eq!(entries.find(&name("ads2.example.com.")), Some(DnxBlockAction::Nxdomain));
+        assert_eq!(entries.find(&name("localhost.")), None);
+    }
+
+    #[test]
+    fn test_rpz_policies() {
+        let rpz = "\
+$TTL 300
+$ORIGIN rpz.local.
+@ IN SOA localhost. root.localhost. 1 3600 600 86400 300
+  IN NS localhost.
+bad.example.com         CNAME .
+*.bad.example.com       CNAME .
+empty.example.com   300 IN CNAME *.
+ok.bad.example.com      CNAME rpz-passthru.
+sink.example.com        A 10.0.0.1
+abs.example.org.rpz.local. CNAME .
+";
+        let entries = parse_rpz(rpz, None);
+
+        assert_eq!(entries.find(&name("bad.example.com.")), Some(DnxBlockAction::Nxdomain));
+        assert_eq!(entries.find(&name("x.bad.example.com.")), Some(DnxBlockAction::Nxdomain));
+        assert_eq!(entries.find(&name("ok.bad.example.com.")), Some(DnxBlockAction::Passthrough));
+        assert_eq!(entries.find(&name("empty.example.com.")), Some(DnxBlockAction::Nodata));
+        assert_eq!(entries.find(&name("sink.example.com.")), Some(DnxBlockAction::Sinkhole("10.0.0.1".parse().unwrap())));
+        assert_eq!(entries.find(&name("abs.example.org.")), Some(DnxBlockAction::Nxdomain));
+        assert_eq!(entries.find(&name("rpz.local.")), None);
+    }
+
+    #[test]
+    fn test_rpz_action_override() {
+        let entries = parse_rpz("bad.example.com CNAME .\n", Some(DnxBlockAction::Nodata));
+
+        assert_eq!(entries.find(&name("bad.example.com.")), Some(DnxBlockAction::Nodata));
+    }
+
+    #[test]
+    fn test_action_answers() {
+        let name = name("ads.example.com.");
+        let sinkhole = DnxBlockAction::Sinkhole("10.0.0.1".parse().unwrap());
+
+        assert_eq!(DnxBlockAction::Nxdomain.answer(&name, RecordType::A), (ResponseCode::NXDomain, Vec::new()));
+        assert_eq!(DnxBlockAction::Nodata.answer(&name, RecordType::A), (ResponseCode::NoError, Vec::new()));
+        assert_eq!(sinkhole.answer(&name, RecordType::AAAA), (ResponseCode::NoError, Vec::new()));
+
+        let (rcode, records) = sinkhole.answer(&name, RecordType::A);
+        assert_eq!(rcode, ResponseCode::NoError);
+        assert_eq!(records[0].data(), Some(&RData::A("10.0.0.1".parse::<std::net::Ipv4Addr>().unwrap().into())));
+    }
+
+    #[test]
+    fn test_first_list_wins_and_reloads() {
+        let dir = std::env::temp_dir();
+        let allow = dir.join(format!("dnx-allow-{}", std::process::id()));
+        let block = dir.join(format!("dnx-block-{}", std::process::id()));
+        fs::write(&allow, "good.example.com\n").unwrap();
+        fs::write(&block, "example.com\n").unwrap();
+
+        let blocklists = DnxBlocklists::new(&[
+            DnxBlocklist {
+                path: allow.clone(),
+                format: DnxBlocklistFormat::Domains,
+                action: Some(DnxBlockAction::Passthrough),
+                subdomains: true,
+            },
+            DnxBlocklist {
+                path: block.clone(),
+                format: DnxBlocklistFormat::Domains,
+                action: None,
+                subdomains: true,
+            },
+        ]);
+
+        assert_eq!(blocklists.find(&name("www.good.example.com.")), Some(DnxBlockAction::Passthrough));
+        assert_eq!(blocklists.find(&name("www.example.com.")), Some(DnxBlockAction::Nxdomain));
+        assert_eq!(blocklists.find(&name("example.org.")), None);
+
+        fs::write(&block, "example.org\n").unwrap();
+        let file = fs::File::options().write(true).open(&block).unwrap();
+        file.set_modified(std::time::SystemTime::now() + Duration::from_secs(60)).unwrap();
+        blocklists.reload_changed();
+
+        assert_eq!(blocklists.find(&name("www.example.com.")), None);
+        assert_eq!(blocklists.find(&name("example.org.")), Some(DnxBlockAction::Nxdomain));
+
+        fs::remove_file(allow).unwrap();
+        fs::remove_file(block).unwrap();
+    }
+}
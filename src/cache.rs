@@ -0,0 +1,353 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use hickory_server::proto::{
+    op::ResponseCode,
+    rr::{LowerName, RData, Record, RecordType},
+};
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+
+use crate::MAX_TRACKED;
+
+/// TTL of stale answers, as RFC 8767 recommends.
+pub const STALE_TTL: u32 = 30;
+/// Answers are refreshed once less than this fraction of their TTL is left.
+const PREFETCH_FRACTION: u32 = 10;
+
+/// Refreshing popular answers in the background shortly before they expire,
+/// so clients don't wait for the upstream when they do.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct DnxPrefetchConfig {
+    /// Refreshes running at once. Answers due while this many are running
+    /// aren't refreshed.
+    pub concurrency: usize,
+    /// Hits an answer needs while it is cached to be refreshed.
+    pub min_hits: u32,
+}
+
+impl Default for DnxPrefetchConfig {
+    fn default() -> Self {
+        DnxPrefetchConfig {
+            concurrency: 4,
+            min_hits: 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DnxCacheKey {
+    pub view: String,
+    pub name: LowerName,
+    pub query_type: RecordType,
+    /// Whether the answer carries the signatures clients setting DO asked for.
+    pub dnssec_ok: bool,
+}
+
+/// An upstream's answer, as sent to clients.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DnxCachedAnswer {
+    pub response_code: ResponseCode,
+    pub answers: Vec<Record>,
+    pub name_servers: Vec<Record>,
+    /// Whether every record of the answer was proven secure with DNSSEC.
+    pub authentic_data: bool,
+    /// Whether the answer went through DNSSEC validation, secure or not.
+    pub validated: bool,
+}
+
+impl DnxCachedAnswer {
+    /// The TTL of the answer: that of its shortest lived record. Negative
+    /// answers live as long as the SOA record in their authority section,
+    /// but no longer than its minimum field, as RFC 2308 says.
+    pub fn ttl(&self) -> Option<u32> {
+        if self.answers.is_empty() {
+            return self.name_servers.iter()
+                .filter_map(|record| match record.data() {
+                    Some(RData::SOA(soa)) => Some(record.ttl().min(soa.minimum())),
+                    _ => None,
+                })
+                .min();
+        }
+
+        self.answers.iter().chain(&self.name_servers).map(Record::ttl).min()
+    }
+
+    fn with_ttl(&self, ttl: u32) -> DnxCachedAnswer {
+        let mut answer = self.clone();
+        answer.answers.iter_mut().chain(&mut answer.name_servers).for_each(|record| {
+            record.set_ttl(record.ttl().min(ttl));
+        });
+        answer
+    }
+}
+
+/// A cached answer given to a client.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DnxCacheHit {
+    pub answer: DnxCachedAnswer,
+    /// The clients the answer applies to, see `CacheEntry::scope`.
+    pub scope: Option<IpNet>,
+    /// Whether the answer is due to be refreshed. Only one hit per answer
+    /// is.
+    pub prefetch: bool,
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    /// The clients the answer applies to, from the ECS scope of the
+    /// upstream's response. `None` for answers that apply to everyone.
+    scope: Option<IpNet>,
+    answer: DnxCachedAnswer,
+    expires: Instant,
+    /// Until when the answer may be served stale, after it expires.
+    stale_until: Instant,
+    /// From when hits on the answer refresh it.
+    prefetch_at: Instant,
+    hits: u32,
+    prefetching: bool,
+}
+
+impl CacheEntry {
+    /// An answer scoped to a network is only given to queries for a subnet
+    /// within it, and unscoped answers only to queries without one, since
+    /// the upstream may tailor the answer to any subnet it is sent.
+    fn applies_to(&self, subnet: Option<IpNet>) -> bool {
+        match (self.scope, subnet) {
+            (None, _) => true,
+            (Some(scope), Some(subnet)) => scope.contains(&subnet),
+            (Some(_), None) => false,
+        }
+    }
+}
+
+/// Answers to forwarded queries. Those queried with EDNS Client Subnet are
+/// kept per scope, so clients in different networks get the answer meant for
+/// theirs. Answers are kept past their expiry for zones that serve stale
+/// answers, see RFC 8767, and popular ones can be refreshed before they
+/// expire.
+#[derive(Debug, Default)]
+pub struct DnxCache {
+    entries: Mutex<HashMap<DnxCacheKey, Vec<CacheEntry>>>,
+    /// Hits an answer needs to be refreshed, if answers are prefetched.
+    prefetch_min_hits: Option<u32>,
+}
+
+impl DnxCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A cache whose answers are due to be refreshed when they near expiry,
+    /// if they had at least `min_hits` hits.
+    pub fn with_prefetch(min_hits: u32) -> Self {
+        DnxCache {
+            entries: Mutex::default(),
+            prefetch_min_hits: Some(min_hits),
+        }
+    }
+
+    /// The cached answer for a query about `subnet`, with TTLs counting down
+    /// from when it was cached.
+    pub fn get(&self, key: &DnxCacheKey, subnet: Option<IpNet>, now: Instant) -> Option<DnxCacheHit> {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.get_mut(key)?.iter_mut()
+            .filter(|entry| entry.expires > now && entry.applies_to(subnet))
+            .max_by_key(|entry| entry.scope.map_or(0, |scope| scope.prefix_len()))?;
+
+        entry.hits = entry.hits.saturating_add(1);
+        let prefetch = match self.prefetch_min_hits {
+            Some(min_hits) => !entry.prefetching && entry.hits >= min_hits && now >= entry.prefetch_at,
+            None => false,
+        };
+        entry.prefetching |= prefetch;
+
+        let remaining = entry.expires.saturating_duration_since(now).as_secs();
+        Some(DnxCacheHit {
+            answer: entry.answer.with_ttl(remaining.try_into().unwrap_or(u32::MAX)),
+            scope: entry.scope,
+            prefetch,
+        })
+    }
+
+    /// Lets the answer cached for `key` and `scope` be prefetched again, after
+    /// a prefetch that didn't run or didn't replace it.
+    pub fn finish_prefetch(&self, key: &DnxCacheKey, scope: Option<IpNet>) {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(entry) = entries.get_mut(key).and_then(|scoped| scoped.iter_mut().find(|entry| entry.scope == scope)) {
+            entry.prefetching = false;
+        }
+    }
+
+    /// An expired answer for a query about `subnet` that may still be served
+    /// stale, with its TTLs set to `STALE_TTL`.
+    pub fn get_stale(&self, key: &DnxCacheKey, subnet: Option<IpNet>, now: Instant) -> Option<DnxCachedAnswer> {
+        let entries = self.entries.lock().unwrap();
+        let entry = entries.get(key)?.iter()
+            .filter(|entry| entry.stale_until > now && entry.applies_to(subnet))
+            .max_by_key(|entry| entry.scope.map_or(0, |scope| scope.prefix_len()))?;
+
+        let mut answer = entry.answer.clone();
+        answer.answers.iter_mut().chain(&mut answer.name_servers).for_each(|record| {
+            record.set_ttl(STALE_TTL);
+        });
+        Some(answer)
+    }
+
+    /// Caches `answer` for the clients in `scope` for `ttl`, and keeps it for
+    /// `stale` after that to serve stale. Any answer cached for the same
+    /// scope is replaced.
+    pub fn insert(&self, key: DnxCacheKey, scope: Option<IpNet>, answer: DnxCachedAnswer, ttl: Duration, stale: Duration, now: Instant) {
+        let mut entries = self.entries.lock().unwrap();
+
+        if entries.len() >= MAX_TRACKED && !entries.contains_key(&key) {
+            entries.retain(|_, scoped| {
+                scoped.retain(|entry| entry.stale_until > now);
+                !scoped.is_empty()
+            });
+            if entries.len() >= MAX_TRACKED {
+                log::debug!("Cache full, not caching {} {}", key.name, key.query_type);
+                return;
+            }
+        }
+
+        let scoped = entries.entry(key).or_default();
+        scoped.retain(|entry| entry.scope != scope && entry.stale_until > now);
+        scoped.push(CacheEntry {
+            scope,
+            answer,
+            expires: now + ttl,
+            stale_until: now + ttl + stale,
+            prefetch_at: now + ttl - ttl / PREFETCH_FRACTION,
+            hits: 0,
+            prefetching: false,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::Ipv4Addr, str::FromStr};
+
+    use hickory_server::proto::rr::{Name, RData};
+
+    use super::*;
+
+    fn key(name: &str) -> DnxCacheKey {
+        DnxCacheKey {
+            view: "".to_string(),
+            name: LowerName::from(Name::from_str(name).unwrap()),
+            query_type: RecordType::A,
+            dnssec_ok: false,
+        }
+    }
+
+    fn answer(ip: [u8; 4], ttl: u32) -> DnxCachedAnswer {
+        let record = Record::from_rdata(Name::from_str("cdn.example.").unwrap(), ttl, RData::A(Ipv4Addr::from(ip).into()));
+        DnxCachedAnswer {
+            response_code: ResponseCode::NoError,
+            answers: vec![record],
+            name_servers: Vec::new(),
+            authentic_data: false,
+            validated: false,
+        }
+    }
+
+    #[test]
+    fn test_cache_by_scope() {
+        let cache = DnxCache::new();
+        let now = Instant::now();
+        let ttl = Duration::from_secs(60);
+
+        cache.insert(key("cdn.example."), Some("192.0.0.0/16".parse().unwrap()), answer([10, 0, 0, 1], 60), ttl, Duration::ZERO, now);
+        cache.insert(key("cdn.example."), Some("198.51.100.0/24".parse().unwrap()), answer([10, 0, 0, 2], 60), ttl, Duration::ZERO, now);
+
+        let get = |subnet: &str| cache.get(&key("cdn.example."), Some(subnet.parse().unwrap()), now).map(|hit| hit.answer);
+        assert_eq!(get("192.0.2.0/24"), Some(answer([10, 0, 0, 1], 60)));
+        assert_eq!(get("198.51.100.0/24"), Some(answer([10, 0, 0, 2], 60)));
+        assert_eq!(get("203.0.113.0/24"), None);
+        assert_eq!(get("192.0.0.0/8"), None);
+        assert_eq!(cache.get(&key("cdn.example."), None, now).map(|hit| hit.answer), None);
+
+        // an unscoped answer applies to every client, but a scoped one is
+        // preferred where it applies
+        cache.insert(key("cdn.example."), None, answer([10, 0, 0, 3], 60), ttl, Duration::ZERO, now);
+        assert_eq!(get("192.0.2.0/24"), Some(answer([10, 0, 0, 1], 60)));
+        assert_eq!(get("203.0.113.0/24"), Some(answer([10, 0, 0, 3], 60)));
+        assert_eq!(cache.get(&key("cdn.example."), None, now).map(|hit| hit.answer), Some(answer([10, 0, 0, 3], 60)));
+    }
+
+    #[test]
+    fn test_cache_expiry_and_ttl() {
+        let cache = DnxCache::new();
+        let now = Instant::now();
+        let subnet = Some("192.0.2.0/24".parse().unwrap());
+
+        cache.insert(key("cdn.example."), subnet, answer([10, 0, 0, 1], 60), Duration::from_secs(60), Duration::ZERO, now);
+        assert_eq!(
+            cache.get(&key("cdn.example."), subnet, now + Duration::from_secs(45)).map(|hit| hit.answer),
+            Some(answer([10, 0, 0, 1], 15)),
+        );
+        assert_eq!(cache.get(&key("cdn.example."), subnet, now + Duration::from_secs(60)).map(|hit| hit.answer), None);
+
+        // a fresh answer for the same scope replaces the old one
+        cache.insert(key("cdn.example."), subnet, answer([10, 0, 0, 2], 30), Duration::from_secs(30), Duration::ZERO, now);
+        assert_eq!(cache.get(&key("cdn.example."), subnet, now).map(|hit| hit.answer), Some(answer([10, 0, 0, 2], 30)));
+        assert_eq!(cache.entries.lock().unwrap()[&key("cdn.example.")].len(), 1);
+    }
+
+    #[test]
+    fn test_cache_serves_stale_within_window() {
+        let cache = DnxCache::new();
+        let now = Instant::now();
+        let ttl = Duration::from_secs(60);
+        let stale = Duration::from_secs(3600);
+
+        cache.insert(key("cdn.example."), None, answer([10, 0, 0, 1], 60), ttl, stale, now);
+        assert_eq!(cache.get_stale(&key("cdn.example."), None, now), Some(answer([10, 0, 0, 1], STALE_TTL)));
+
+        let expired = now + Duration::from_secs(120);
+        assert_eq!(cache.get(&key("cdn.example."), None, expired).map(|hit| hit.answer), None);
+        assert_eq!(cache.get_stale(&key("cdn.example."), None, expired), Some(answer([10, 0, 0, 1], STALE_TTL)));
+        assert_eq!(cache.get_stale(&key("cdn.example."), None, now + ttl + stale), None);
+
+        // answers without a stale window are gone once they expire
+        cache.insert(key("www.example."), None, answer([10, 0, 0, 2], 60), ttl, Duration::ZERO, now);
+        assert_eq!(cache.get_stale(&key("www.example."), None, expired), None);
+    }
+
+    #[test]
+    fn test_cache_prefetches_popular_answers_once() {
+        let cache = DnxCache::with_prefetch(2);
+        let now = Instant::now();
+        let ttl = Duration::from_secs(100);
+
+        cache.insert(key("cdn.example."), None, answer([10, 0, 0, 1], 100), ttl, Duration::ZERO, now);
+        let prefetch = |at: u64| cache.get(&key("cdn.example."), None, now + Duration::from_secs(at)).unwrap().prefetch;
+
+        // not yet near expiry
+        assert!(!prefetch(50));
+        // near expiry, but only after enough hits
+        assert!(prefetch(95));
+        assert!(!prefetch(96));
+
+        cache.insert(key("cdn.example."), None, answer([10, 0, 0, 1], 100), ttl, Duration::ZERO, now);
+        assert!(!prefetch(95));
+
+        // until a prefetch that couldn't replace the answer finishes
+        assert!(prefetch(96));
+        cache.finish_prefetch(&key("cdn.example."), None);
+        assert!(prefetch(97));
+
+        // caches without prefetching never refresh
+        let cache = DnxCache::new();
+        cache.insert(key("cdn.example."), None, answer([10, 0, 0, 1], 100), ttl, Duration::ZERO, now);
+        for _ in 0..3 {
+            assert!(!cache.get(&key("cdn.example."), None, now + Duration::from_secs(95)).unwrap().prefetch);
+        }
+    }
+}
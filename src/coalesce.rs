@@ -0,0 +1,126 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    hash::Hash,
+    sync::Mutex,
+};
+
+use tokio::sync::watch;
+
+/// Lookups in flight, so that identical lookups running at once share a
+/// single upstream request.
+#[derive(Debug)]
+pub struct DnxCoalescer<K, V> {
+    inflight: Mutex<HashMap<K, watch::Receiver<Option<V>>>>,
+}
+
+impl<K, V> Default for DnxCoalescer<K, V> {
+    fn default() -> Self {
+        DnxCoalescer {
+            inflight: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+/// Removes a lookup from those in flight once it completes or is cancelled.
+struct Inflight<'a, K: Eq + Hash, V> {
+    coalescer: &'a DnxCoalescer<K, V>,
+    key: &'a K,
+}
+
+impl<K: Eq + Hash, V> Drop for Inflight<'_, K, V> {
+    fn drop(&mut self) {
+        self.coalescer.inflight.lock().unwrap().remove(self.key);
+    }
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> DnxCoalescer<K, V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs `lookup`, unless a lookup for `key` is already running, in which
+    /// case its result is waited for instead. Returns the result and whether
+    /// it came from another lookup. If the running lookup is cancelled,
+    /// `lookup` runs after all.
+    pub async fn run<F: Future<Output = V>>(&self, key: K, lookup: F) -> (V, bool) {
+        let sender = {
+            let mut inflight = self.inflight.lock().unwrap();
+            match inflight.get(&key) {
+                Some(receiver) => Err(receiver.clone()),
+                None => {
+                    let (sender, receiver) = watch::channel(None);
+                    inflight.insert(key.clone(), receiver);
+                    Ok(sender)
+                }
+            }
+        };
+
+        let sender = match sender {
+            Ok(sender) => sender,
+            Err(mut receiver) => {
+                if let Ok(value) = receiver.wait_for(Option::is_some).await {
+                    if let Some(value) = value.clone() {
+                        return (value, true);
+                    }
+                }
+                return (lookup.await, false);
+            }
+        };
+
+        let _inflight = Inflight { coalescer: self, key: &key };
+        let value = lookup.await;
+        sender.send_replace(Some(value.clone()));
+        (value, false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_coalescer_shares_concurrent_lookups() {
+        let coalescer = DnxCoalescer::new();
+        let lookups = &AtomicUsize::new(0);
+        let lookup = |value: u32| async move {
+            lookups.fetch_add(1, Ordering::Relaxed);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            value
+        };
+
+        let (first, second, other) = tokio::join!(
+            coalescer.run("a", lookup(1)),
+            coalescer.run("a", lookup(2)),
+            coalescer.run("b", lookup(3)),
+        );
+        assert_eq!(first, (1, false));
+        assert_eq!(second, (1, true));
+        assert_eq!(other, (3, false));
+        assert_eq!(lookups.load(Ordering::Relaxed), 2);
+
+        // finished lookups aren't shared
+        assert_eq!(coalescer.run("a", lookup(4)).await, (4, false));
+        assert!(coalescer.inflight.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_coalescer_runs_lookup_when_shared_one_is_cancelled() {
+        let coalescer = DnxCoalescer::new();
+        let cancelled = tokio::time::timeout(Duration::from_millis(10), coalescer.run("a", async {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            1
+        }));
+
+        let (cancelled, waiter) = tokio::join!(cancelled, async {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+            coalescer.run("a", async { 2 }).await
+        });
+        assert!(cancelled.is_err());
+        assert_eq!(waiter, (2, false));
+        assert!(coalescer.inflight.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,839 @@
+use std::{
+    collections::HashSet,
+    fmt,
+    fs,
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use data_encoding::{BASE32_DNSSEC, BASE64};
+use hickory_resolver::{
+    config::{NameServerConfig, NameServerConfigGroup, Protocol, ResolverOpts},
+    error::ResolveError,
+    name_server::{ConnectionProvider, NameServerPool, TokioConnectionProvider},
+};
+use hickory_server::proto::{
+    op::{Edns, Message, Query},
+    rr::{
+        dnssec::{
+            rdata::{DNSSECRData, DNSKEY, NSEC3, RRSIG},
+            tbs,
+            Algorithm,
+            DnsSecResult,
+            KeyFormat,
+            PublicKeyBuf,
+            SigSigner,
+            TrustAnchor,
+            Verifier,
+        },
+        DNSClass,
+        Name,
+        RData,
+        Record,
+        RecordType,
+    },
+    xfer::{DnsHandle, DnsRequest, DnsRequestOptions, DnsResponse, DnssecDnsHandle, FirstAnswer, RetryDnsHandle},
+};
+use serde::{Deserialize, Serialize};
+use tokio::time::timeout;
+
+use crate::upstream;
+
+/// DNSSEC validation of answers from the default server. Zones are never
+/// validated, as their NAT rewrites would break the signatures.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct DnxDnssecConfig {
+    /// A zone file of DNSKEY records to trust instead of the root zone's
+    /// keys, such as those of a lab zone signed for testing.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trust_anchors: Option<PathBuf>,
+}
+
+impl DnxDnssecConfig {
+    /// The keys validation starts from: those in `trust_anchors`, or the
+    /// root zone's without it.
+    pub fn trust_anchor(&self) -> io::Result<DnxTrustAnchor> {
+        match self.trust_anchors {
+            Some(ref path) => load_trust_anchor(path),
+            None => Ok(DnxTrustAnchor::default()),
+        }
+    }
+}
+
+/// Trusted keys, and the zones they belong to. Names in none of the zones
+/// can't be validated.
+#[derive(Clone)]
+pub struct DnxTrustAnchor {
+    keys: TrustAnchor,
+    zones: Vec<Name>,
+}
+
+impl Default for DnxTrustAnchor {
+    /// The root zone's keys.
+    fn default() -> Self {
+        DnxTrustAnchor {
+            keys: TrustAnchor::default(),
+            zones: vec![Name::root()],
+        }
+    }
+}
+
+impl DnxTrustAnchor {
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+}
+
+/// Joins the lines of records spanning several within parentheses, and
+/// drops comments.
+fn logical_lines(text: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    let mut depth = 0usize;
+
+    for physical in text.lines() {
+        let physical = physical.split(';').next().unwrap_or_default();
+        for c in physical.chars() {
+            match c {
+                '(' => depth += 1,
+                ')' => depth = depth.saturating_sub(1),
+                c => line.push(c),
+            }
+        }
+
+        if depth == 0 {
+            lines.push(std::mem::take(&mut line));
+        } else {
+            line.push(' ');
+        }
+    }
+    lines.push(line);
+
+    lines.retain(|line| !line.trim().is_empty());
+    lines
+}
+
+/// Reads the DNSKEY records of a zone file, in the presentation format `dig`
+/// prints them in, into a trust anchor. Other records in the file are
+/// ignored.
+pub fn load_trust_anchor(path: &Path) -> io::Result<DnxTrustAnchor> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let text = fs::read_to_string(path)?;
+
+    let mut trust_anchor = DnxTrustAnchor { keys: TrustAnchor::new(), zones: Vec::new() };
+    for line in logical_lines(&text) {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let Some(position) = fields.iter().position(|field| field.eq_ignore_ascii_case("DNSKEY")) else {
+            continue;
+        };
+
+        let zone = fields.first().filter(|_| position > 0)
+            .ok_or_else(|| invalid(format!("DNSKEY record without an owner: {line}")))?;
+        let zone = Name::from_ascii(zone)
+            .map_err(|e| invalid(format!("invalid DNSKEY record {line}: {e}")))?;
+        if !trust_anchor.zones.contains(&zone) {
+            trust_anchor.zones.push(zone);
+        }
+
+        // flags, protocol and algorithm come before the key
+        let key = fields.get(position + 4..).filter(|key| !key.is_empty())
+            .ok_or_else(|| invalid(format!("DNSKEY record without a key: {line}")))?
+            .concat();
+        let key = BASE64.decode(key.as_bytes())
+            .map_err(|e| invalid(format!("invalid DNSKEY record {line}: {e}")))?;
+        trust_anchor.keys.insert_trust_anchor(&PublicKeyBuf::new(key));
+    }
+
+    if trust_anchor.is_empty() {
+        return Err(invalid("no DNSKEY records found".to_string()));
+    }
+    Ok(trust_anchor)
+}
+
+type ValidatingHandle = DnssecDnsHandle<RetryDnsHandle<NameServerPool<TokioConnectionProvider>>>;
+
+/// A validated answer.
+#[derive(Debug)]
+pub struct DnxValidated {
+    pub message: Message,
+    /// Whether every RRset of the answer was signed. Unsigned ones are only
+    /// let through from zones proven unsigned.
+    pub secure: bool,
+}
+
+/// The records of an answer with one name and type, and their signatures.
+struct Rrset {
+    name: Name,
+    record_type: RecordType,
+    records: Vec<Record>,
+    rrsigs: Vec<RRSIG>,
+}
+
+/// Groups `records` into RRsets, in the order they first appear.
+fn rrsets(records: &[Record]) -> Vec<Rrset> {
+    let mut rrsets: Vec<Rrset> = Vec::new();
+    for record in records.iter().filter(|record| record.record_type() != RecordType::RRSIG) {
+        let rrset = rrsets.iter_mut()
+            .find(|rrset| rrset.name == *record.name() && rrset.record_type == record.record_type());
+        match rrset {
+            Some(rrset) => rrset.records.push(record.clone()),
+            None => rrsets.push(Rrset {
+                name: record.name().clone(),
+                record_type: record.record_type(),
+                records: vec![record.clone()],
+                rrsigs: Vec::new(),
+            }),
+        }
+    }
+
+    for record in records {
+        if let Some(RData::DNSSEC(DNSSECRData::RRSIG(rrsig))) = record.data() {
+            let rrset = rrsets.iter_mut()
+                .find(|rrset| rrset.name == *record.name() && rrset.record_type == rrsig.type_covered());
+            if let Some(rrset) = rrset {
+                rrset.rrsigs.push(rrsig.clone());
+            }
+        }
+    }
+    rrsets
+}
+
+fn bogus(name: &Name, reason: impl fmt::Display) -> ResolveError {
+    ResolveError::from(format!("bogus answer for {name}: {reason}"))
+}
+
+/// Whether `name` falls between `owner` and `next`, in the order of an NSEC
+/// or NSEC3 chain. The last link of a chain wraps around to the first.
+fn covers<T: Ord + ?Sized>(owner: &T, next: &T, name: &T) -> bool {
+    if owner < next {
+        owner < name && name < next
+    } else {
+        owner < name || name < next
+    }
+}
+
+/// What the types an NSEC or NSEC3 record lists for a name without DS
+/// records say: whether the name is a delegation, which must then be
+/// unsigned.
+fn is_delegation(name: &Name, types: &[RecordType]) -> Result<bool, ResolveError> {
+    if types.contains(&RecordType::DS) {
+        return Err(bogus(name, "DS records were denied but exist"));
+    }
+    Ok(types.contains(&RecordType::NS) && !types.contains(&RecordType::SOA))
+}
+
+/// The NSEC3 hash of `name` with `nsec3`'s parameters, as it appears in
+/// owner names.
+fn nsec3_hash(nsec3: &NSEC3, name: &Name) -> Result<String, ResolveError> {
+    let hash = nsec3.hash_algorithm().hash(nsec3.salt(), &name.to_lowercase(), nsec3.iterations())?;
+    Ok(BASE32_DNSSEC.encode(hash.as_ref()))
+}
+
+/// The hash an NSEC3 record is for: the first label of its name.
+fn nsec3_owner(record: &Record) -> String {
+    record.name().iter().next()
+        .map(|label| String::from_utf8_lossy(label).to_ascii_lowercase())
+        .unwrap_or_default()
+}
+
+/// Looks up and validates answers from one upstream. Answers are secure when
+/// all their RRsets verify up to the trust anchor, and insecure when the
+/// zones of the unsigned ones are proven unsigned by the DS records, or
+/// their signed denial, on the way down from it. Anything else is bogus.
+#[derive(Clone)]
+pub struct DnxValidator {
+    /// Looks up validated DNSKEY records.
+    handle: ValidatingHandle,
+    servers: Vec<NameServerConfig>,
+    options: ResolverOpts,
+    connections: TokioConnectionProvider,
+    anchors: Arc<[Name]>,
+}
+
+impl DnxValidator {
+    pub fn new(servers: Vec<NameServerConfig>, options: ResolverOpts, trust_anchor: DnxTrustAnchor) -> Self {
+        // signed answers often exceed a datagram, so TCP is there to retry
+        // truncated ones
+        let tcp = servers.iter()
+            .filter(|server| server.protocol == Protocol::Udp)
+            .map(|server| NameServerConfig::new(server.socket_addr, Protocol::Tcp))
+            .filter(|tcp| !servers.contains(tcp))
+            .collect::<Vec<_>>();
+        let servers = [servers, tcp].concat();
+        let attempts = options.attempts;
+        let pool = NameServerPool::from_config(
+            NameServerConfigGroup::from(servers.clone()),
+            options.clone(),
+            TokioConnectionProvider::default(),
+        );
+
+        DnxValidator {
+            handle: DnssecDnsHandle::with_trust_anchor(RetryDnsHandle::new(pool, attempts), trust_anchor.keys),
+            servers,
+            options,
+            connections: TokioConnectionProvider::default(),
+            anchors: trust_anchor.zones.into(),
+        }
+    }
+
+    /// The validated response to `query`. Bogus answers are errors, and
+    /// negative answers `NoRecordsFound` errors like those of a resolver,
+    /// which are passed on without being validated.
+    pub async fn lookup(&self, query: Query) -> Result<DnxValidated, ResolveError> {
+        let response = ResolveError::from_response(self.query(query).await?, false)?;
+
+        let mut secure = true;
+        for rrset in rrsets(response.answers()) {
+            if !rrset.rrsigs.is_empty() {
+                if !self.verify(&rrset).await {
+                    return Err(bogus(&rrset.name, format!("{} records don't verify", rrset.record_type)));
+                }
+            } else if self.is_signed(&rrset.name).await? {
+                return Err(bogus(&rrset.name, format!("{} records are unsigned in a signed zone", rrset.record_type)));
+            } else {
+                secure = false;
+            }
+        }
+
+        Ok(DnxValidated { message: response.into_message(), secure })
+    }
+
+    /// Asks the servers in turn for `query`, with DNSSEC records. Unlike
+    /// through a resolver, negative responses come back whole, with the
+    /// NSEC or NSEC3 records denying the name.
+    async fn query(&self, query: Query) -> Result<DnsResponse, ResolveError> {
+        let mut message = upstream::query_message(query);
+        let mut edns = Edns::new();
+        edns.set_max_payload(upstream::EDNS_PAYLOAD);
+        edns.set_dnssec_ok(true);
+        message.set_edns(edns);
+
+        let mut error = ResolveError::from("no name servers to query");
+        for server in &self.servers {
+            let request = DnsRequest::new(message.clone(), DnsRequestOptions::default());
+            let exchange = async {
+                let connection = self.connections.new_connection(server, &self.options).await?;
+                connection.send(request).first_answer().await
+            };
+            match timeout(self.options.timeout, exchange).await {
+                Ok(Ok(response)) if !response.truncated() => return Ok(response),
+                Ok(Ok(_)) => error = format!("response from {} truncated", server.socket_addr).into(),
+                Ok(Err(e)) => error = e,
+                Err(_) => error = format!("{} timed out", server.socket_addr).into(),
+            }
+        }
+        Err(error)
+    }
+
+    /// The keys of `zone` that chain up to the trust anchor.
+    async fn keys(&self, zone: &Name) -> Result<Vec<DNSKEY>, ResolveError> {
+        let mut options = DnsRequestOptions::default();
+        options.use_edns = true;
+
+        let query = Query::query(zone.clone(), RecordType::DNSKEY);
+        let response = self.handle.lookup(query, options).first_answer().await?;
+        Ok(response.answers().iter()
+            .filter_map(|record| match record.data() {
+                Some(RData::DNSSEC(DNSSECRData::DNSKEY(key))) => Some(key.clone()),
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// Whether one of the signatures of `rrset` verifies with a trusted key
+    /// of a zone it's in.
+    async fn verify(&self, rrset: &Rrset) -> bool {
+        for rrsig in rrset.rrsigs.iter().filter(|rrsig| rrsig.signer_name().zone_of(&rrset.name)) {
+            let keys = match self.keys(rrsig.signer_name()).await {
+                Ok(keys) => keys,
+                Err(e) => {
+                    log::debug!("No trusted keys for {}: {}", rrsig.signer_name(), e);
+                    continue;
+                }
+            };
+            if keys.iter().any(|key| key.verify_rrsig(&rrset.name, DNSClass::IN, rrsig, &rrset.records).is_ok()) {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Whether `name` is in a signed zone: whether every zone cut between it
+    /// and the closest trust anchor above it has signed DS records. Names no
+    /// trust anchor is above aren't.
+    async fn is_signed(&self, name: &Name) -> Result<bool, ResolveError> {
+        let anchor = self.anchors.iter()
+            .filter(|anchor| anchor.zone_of(name))
+            .max_by_key(|anchor| anchor.num_labels());
+        let Some(anchor) = anchor else {
+            return Ok(false);
+        };
+
+        for labels in anchor.num_labels() + 1..=name.num_labels() {
+            let child = name.trim_to(labels as usize);
+            let response = self.query(Query::query(child.clone(), RecordType::DS)).await?;
+            let ds = rrsets(response.answers()).into_iter()
+                .find(|rrset| rrset.name == child && rrset.record_type == RecordType::DS);
+            match ds {
+                Some(ds) if self.verify(&ds).await => {}
+                Some(_) => return Err(bogus(&child, "DS records don't verify")),
+                None if self.is_unsigned_delegation(&child, &response).await? => return Ok(false),
+                None => {}
+            }
+        }
+        Ok(true)
+    }
+
+    /// Whether the signed NSEC or NSEC3 records of a response denying DS
+    /// records for `name` prove it an unsigned delegation, rather than no
+    /// zone cut at all. Errors if they prove neither.
+    async fn is_unsigned_delegation(&self, name: &Name, response: &Message) -> Result<bool, ResolveError> {
+        let denials: Vec<Rrset> = rrsets(response.name_servers()).into_iter()
+            .filter(|rrset| matches!(rrset.record_type, RecordType::NSEC | RecordType::NSEC3))
+            .collect();
+        for denial in &denials {
+            if !self.verify(denial).await {
+                return Err(bogus(&denial.name, format!("{} records don't verify", denial.record_type)));
+            }
+        }
+
+        let records = || denials.iter().flat_map(|denial| &denial.records);
+        // a record for the name itself lists its types
+        for record in records() {
+            match record.data() {
+                Some(RData::DNSSEC(DNSSECRData::NSEC(nsec))) if record.name() == name => {
+                    return is_delegation(name, nsec.type_bit_maps());
+                }
+                Some(RData::DNSSEC(DNSSECRData::NSEC3(nsec3))) if nsec3_owner(record) == nsec3_hash(nsec3, name)? => {
+                    return is_delegation(name, nsec3.type_bit_maps());
+                }
+                _ => {}
+            }
+        }
+
+        // one covering the name proves it doesn't exist, unless NSEC3 opts
+        // out of listing unsigned delegations
+        for record in records() {
+            match record.data() {
+                Some(RData::DNSSEC(DNSSECRData::NSEC(nsec))) if covers(record.name(), nsec.next_domain_name(), name) => {
+                    return Ok(false);
+                }
+                Some(RData::DNSSEC(DNSSECRData::NSEC3(nsec3))) => {
+                    let next = BASE32_DNSSEC.encode(nsec3.next_hashed_owner_name());
+                    if covers(nsec3_owner(record).as_str(), &next, &nsec3_hash(nsec3, name)?) {
+                        return Ok(nsec3.opt_out());
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Err(bogus(name, "DS records were denied without proof"))
+    }
+}
+
+/// What to do with the signatures of RRsets a NAT rule rewrote, which no
+/// longer verify.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DnxNatDnssec {
+    /// Remove the signatures, and the AD flag of the response.
+    #[default]
+    Strip,
+    /// Sign the rewritten RRsets again with a local key.
+    Resign(DnxSigningKey),
+}
+
+impl DnxNatDnssec {
+    pub fn is_strip(&self) -> bool {
+        *self == DnxNatDnssec::Strip
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DnxSigningKey {
+    /// A PKCS#8 private key, in DER.
+    pub key_file: PathBuf,
+    pub algorithm: Algorithm,
+    /// The zone the key belongs to. Defaults to the zone the NAT rule is
+    /// configured on.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signer_name: Option<String>,
+}
+
+/// A signing key loaded from a `DnxSigningKey`.
+#[derive(Clone)]
+pub struct DnxResigner {
+    signer: Arc<SigSigner>,
+}
+
+impl fmt::Debug for DnxResigner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DnxResigner")
+            .field("signer_name", self.signer.signer_name())
+            .field("algorithm", &self.signer.algorithm())
+            .finish()
+    }
+}
+
+impl DnxResigner {
+    pub fn load(key: &DnxSigningKey) -> io::Result<Self> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+
+        let signer_name = key.signer_name.as_deref().ok_or_else(|| invalid("no signer name".to_string()))?;
+        let signer_name = Name::from_utf8(signer_name).map_err(|e| invalid(e.to_string()))?;
+        let bytes = fs::read(&key.key_file)?;
+        let key_pair = KeyFormat::Pkcs8.decode_key(&bytes, None, key.algorithm).map_err(|e| invalid(e.to_string()))?;
+        let dnskey = key_pair.to_dnskey(key.algorithm).map_err(|e| invalid(e.to_string()))?;
+
+        Ok(DnxResigner {
+            signer: Arc::new(SigSigner::dnssec(dnskey, key_pair, signer_name, Duration::ZERO)),
+        })
+    }
+
+    /// A signature of `rrset` replacing `rrsig`, valid for the same period.
+    fn resign(&self, name: &Name, ttl: u32, rrsig: &RRSIG, rrset: &[Record]) -> DnsSecResult<Record> {
+        let key_tag = self.signer.calculate_key_tag()?;
+        let signed = |sig| RRSIG::new(
+            rrsig.type_covered(),
+            self.signer.algorithm(),
+            rrsig.num_labels(),
+            rrsig.original_ttl(),
+            rrsig.sig_expiration(),
+            rrsig.sig_inception(),
+            key_tag,
+            self.signer.signer_name().clone(),
+            sig,
+        );
+
+        let unsigned = signed(Vec::new());
+        let tbs = tbs::rrset_tbs_with_sig(name, rrset[0].dns_class(), &unsigned, rrset)?;
+        let rrsig = signed(self.signer.sign(&tbs)?);
+        Ok(Record::from_rdata(name.clone(), ttl, RData::DNSSEC(DNSSECRData::RRSIG(rrsig))))
+    }
+}
+
+/// Deals with the signatures of the RRsets in `rewritten`, which no longer
+/// verify: they are re-signed with `resigner`, or removed without one.
+/// Returns whether any signature was removed.
+pub fn fix_signatures(records: &mut Vec<Record>, rewritten: &HashSet<(Name, RecordType)>, resigner: Option<&DnxResigner>) -> bool {
+    let mut stripped = false;
+    let mut fixed = Vec::with_capacity(records.len());
+
+    for record in records.iter() {
+        let rrsig = match record.data() {
+            Some(RData::DNSSEC(DNSSECRData::RRSIG(rrsig))) => rrsig,
+            _ => {
+                fixed.push(record.clone());
+                continue;
+            }
+        };
+        if !rewritten.contains(&(record.name().clone(), rrsig.type_covered())) {
+            fixed.push(record.clone());
+            continue;
+        }
+
+        let Some(resigner) = resigner else {
+            stripped = true;
+            continue;
+        };
+
+        let rrset: Vec<Record> = records.iter()
+            .filter(|other| other.name() == record.name() && other.record_type() == rrsig.type_covered())
+            .cloned()
+            .collect();
+        match resigner.resign(record.name(), record.ttl(), rrsig, &rrset) {
+            Ok(resigned) => fixed.push(resigned),
+            Err(e) => {
+                log::error!("Removing signature of {} {} that failed to re-sign: {}", record.name(), rrsig.type_covered(), e);
+                stripped = true;
+            }
+        }
+    }
+
+    *records = fixed;
+    stripped
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::{Ipv4Addr, SocketAddr}, str::FromStr, time::Duration};
+
+    use hickory_server::{
+        authority::{AuthorityObject, Catalog, ZoneType},
+        proto::rr::{
+            dnssec::KeyPair,
+            rdata::{A, NS, SOA, TXT},
+        },
+        server::{Request, RequestHandler, ResponseHandler, ResponseInfo},
+        store::in_memory::InMemoryAuthority,
+        ServerFuture,
+    };
+    use tokio::net::UdpSocket;
+
+    use super::*;
+
+    fn signing_key() -> SigSigner {
+        let algorithm = Algorithm::ECDSAP256SHA256;
+        let pkcs8 = KeyPair::generate_pkcs8(algorithm).unwrap();
+        let key = KeyFormat::Pkcs8.decode_key(&pkcs8, None, algorithm).unwrap();
+        let dnskey = key.to_dnskey(algorithm).unwrap();
+        SigSigner::dnssec(dnskey, key, Name::from_str("lab.example.").unwrap(), Duration::from_secs(86400))
+    }
+
+    /// A zone with a SOA record and `www` pointing to `ip`.
+    fn zone(origin: &str, ip: Ipv4Addr) -> InMemoryAuthority {
+        let origin = Name::from_str(origin).unwrap();
+        let mut authority = InMemoryAuthority::empty(origin.clone(), ZoneType::Primary, false);
+        let soa = SOA::new(origin.clone(), Name::from_str("hostmaster.lab.example.").unwrap(), 1, 3600, 600, 86400, 60);
+        authority.upsert_mut(Record::from_rdata(origin.clone(), 300, RData::SOA(soa)), 1);
+        let www = Name::from_str("www").unwrap().append_domain(&origin).unwrap();
+        authority.upsert_mut(Record::from_rdata(www, 300, RData::A(A(ip))), 1);
+        authority
+    }
+
+    fn catalog(authorities: &[&Arc<InMemoryAuthority>]) -> Catalog {
+        let mut catalog = Catalog::new();
+        for authority in authorities {
+            let origin = authority.origin().clone();
+            catalog.upsert(origin, Box::new(Arc::clone(authority)) as Box<dyn AuthorityObject>);
+        }
+        catalog
+    }
+
+    /// Answers DS queries from the parent side of zone cuts, like a
+    /// recursive resolver, which a catalog of both zones wouldn't.
+    struct Upstream {
+        parents: Catalog,
+        zones: Catalog,
+    }
+
+    #[async_trait::async_trait]
+    impl RequestHandler for Upstream {
+        async fn handle_request<R: ResponseHandler>(&self, request: &Request, response_handle: R) -> ResponseInfo {
+            match request.query().query_type() {
+                RecordType::DS => self.parents.handle_request(request, response_handle).await,
+                _ => self.zones.handle_request(request, response_handle).await,
+            }
+        }
+    }
+
+    /// Serves lab.example. signed with `signer` over UDP, and its unsigned
+    /// subzone unsigned.lab.example.
+    async fn signed_upstream(signer: SigSigner) -> SocketAddr {
+        let mut parent = zone("lab.example.", Ipv4Addr::new(10, 0, 0, 10));
+        let unsigned = Name::from_str("unsigned.lab.example.").unwrap();
+        let ns = Name::from_str("ns.unsigned.lab.example.").unwrap();
+        parent.upsert_mut(Record::from_rdata(unsigned, 300, RData::NS(NS(ns))), 1);
+        parent.add_zone_signing_key_mut(signer).unwrap();
+        parent.secure_zone_mut().unwrap();
+        let parent = Arc::new(parent);
+        let child = Arc::new(zone("unsigned.lab.example.", Ipv4Addr::new(10, 0, 0, 20)));
+        let upstream = Upstream {
+            parents: catalog(&[&parent]),
+            zones: catalog(&[&parent, &child]),
+        };
+
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        let mut server = ServerFuture::new(upstream);
+        server.register_socket(socket);
+        tokio::spawn(async move { server.block_until_done().await });
+        addr
+    }
+
+    fn addresses(message: &Message) -> Vec<&A> {
+        message.answers().iter()
+            .filter_map(|record| record.data().and_then(RData::as_a))
+            .collect()
+    }
+
+    fn trust_anchor_file(name: &str, signer: &SigSigner) -> PathBuf {
+        let key = signer.key().to_dnskey(signer.algorithm()).unwrap();
+        let record = Record::from_rdata(signer.signer_name().clone(), 300, RData::DNSSEC(DNSSECRData::DNSKEY(key)));
+        let path = std::env::temp_dir().join(format!("dnx-anchors-{}-{}", name, std::process::id()));
+        fs::write(&path, format!("{record}\n")).unwrap();
+        path
+    }
+
+    fn query() -> Query {
+        Query::query(Name::from_str("www.lab.example.").unwrap(), RecordType::A)
+    }
+
+    #[tokio::test]
+    async fn test_validates_against_trust_anchor_file() {
+        let signer = signing_key();
+        let path = trust_anchor_file("valid", &signer);
+        let config = DnxDnssecConfig { trust_anchors: Some(path.clone()) };
+        let trust_anchor = config.trust_anchor().unwrap();
+        assert_eq!(trust_anchor.len(), 1);
+
+        let server = signed_upstream(signer).await;
+        let validator = DnxValidator::new(vec![NameServerConfig::new(server, Protocol::Udp)], ResolverOpts::default(), trust_anchor);
+        let response = validator.lookup(query()).await.unwrap();
+        assert!(response.secure);
+        assert_eq!(addresses(&response.message), vec![&A(Ipv4Addr::new(10, 0, 0, 10))]);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rejects_answers_signed_by_untrusted_key() {
+        let path = trust_anchor_file("untrusted", &signing_key());
+        let trust_anchor = load_trust_anchor(&path).unwrap();
+
+        let server = signed_upstream(signing_key()).await;
+        let validator = DnxValidator::new(vec![NameServerConfig::new(server, Protocol::Udp)], ResolverOpts::default(), trust_anchor);
+        assert!(validator.lookup(query()).await.is_err());
+        // nor can the subzone be proven unsigned
+        let unsigned = Query::query(Name::from_str("www.unsigned.lab.example.").unwrap(), RecordType::A);
+        assert!(validator.lookup(unsigned).await.is_err());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_passes_unsigned_zones_through_insecure() {
+        let signer = signing_key();
+        let path = trust_anchor_file("insecure", &signer);
+        let trust_anchor = load_trust_anchor(&path).unwrap();
+
+        let server = signed_upstream(signer).await;
+        let validator = DnxValidator::new(vec![NameServerConfig::new(server, Protocol::Udp)], ResolverOpts::default(), trust_anchor);
+        let unsigned = Query::query(Name::from_str("www.unsigned.lab.example.").unwrap(), RecordType::A);
+        let response = validator.lookup(unsigned).await.unwrap();
+        assert!(!response.secure);
+        assert_eq!(addresses(&response.message), vec![&A(Ipv4Addr::new(10, 0, 0, 20))]);
+
+        // names outside every trust anchor can't be validated either
+        assert!(!validator.is_signed(&Name::from_str("www.other.example.").unwrap()).await.unwrap());
+        assert!(validator.is_signed(&Name::from_str("www.lab.example.").unwrap()).await.unwrap());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_trust_anchor_file_formats() {
+        let path = std::env::temp_dir().join(format!("dnx-anchors-formats-{}", std::process::id()));
+        fs::write(&path, "\
+; the root zone's KSK, as dig prints it
+.  172800  IN  DNSKEY  257 3 8 (
+        AwEAAaz/tAm8yTn4Mfeh5eyI96WSVexTBAvkMgJzkKTO ; key
+        iW1vkIbzxeF3+/4RgWOq7HrxRixHlFlExOLAJr5emLvN )
+www.lab.example. 300 IN A 10.0.0.10
+lab.example. DNSKEY 256 3 13 oJMRESz5E4gYzS/q6XDrvU1qMPYIjCWzJaOau8XNEZeq CvKu5JLsz8WiWf4s7WPw
+").unwrap();
+        assert_eq!(load_trust_anchor(&path).unwrap().len(), 2);
+
+        fs::write(&path, "lab.example. DNSKEY 257 3 13 not-base64\n").unwrap();
+        assert!(load_trust_anchor(&path).is_err());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_trust_anchor_file_without_keys() {
+        let path = std::env::temp_dir().join(format!("dnx-anchors-empty-{}", std::process::id()));
+        fs::write(&path, "www.lab.example. 300 IN A 10.0.0.10\n").unwrap();
+        assert!(load_trust_anchor(&path).is_err());
+        fs::remove_file(path).unwrap();
+    }
+
+    fn signed_rrsets(signer: &DnxResigner) -> Vec<Record> {
+        let name = Name::from_str("www.lab.example.").unwrap();
+        let a = vec![
+            Record::from_rdata(name.clone(), 300, RData::A(A(Ipv4Addr::new(192, 168, 0, 10)))),
+            Record::from_rdata(name.clone(), 300, RData::A(A(Ipv4Addr::new(192, 168, 0, 11)))),
+        ];
+        let txt = vec![Record::from_rdata(name.clone(), 300, RData::TXT(TXT::new(vec!["lab".to_string()])))];
+
+        let mut records = Vec::new();
+        for rrset in [a, txt] {
+            let template = RRSIG::new(rrset[0].record_type(), Algorithm::ECDSAP256SHA256, 3, 300, 2_000_000_000, 1_000_000_000, 0, name.clone(), Vec::new());
+            let rrsig = signer.resign(&name, 300, &template, &rrset).unwrap();
+            records.extend(rrset);
+            records.push(rrsig);
+        }
+        records
+    }
+
+    fn rewrite(records: &mut [Record]) -> HashSet<(Name, RecordType)> {
+        for record in records.iter_mut().filter(|record| record.record_type() == RecordType::A) {
+            record.set_data(Some(RData::A(A(Ipv4Addr::new(10, 0, 0, 10)))));
+        }
+        HashSet::from([(Name::from_str("www.lab.example.").unwrap(), RecordType::A)])
+    }
+
+    fn rrsig_covering(records: &[Record], record_type: RecordType) -> Option<&RRSIG> {
+        records.iter().find_map(|record| match record.data() {
+            Some(RData::DNSSEC(DNSSECRData::RRSIG(rrsig))) if rrsig.type_covered() == record_type => Some(rrsig),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn test_fix_signatures_strips_rewritten_rrsets() {
+        let upstream = DnxResigner { signer: Arc::new(signing_key()) };
+        let mut records = signed_rrsets(&upstream);
+        let rewritten = rewrite(&mut records);
+
+        assert!(fix_signatures(&mut records, &rewritten, None));
+        assert!(rrsig_covering(&records, RecordType::A).is_none());
+        assert!(rrsig_covering(&records, RecordType::TXT).is_some());
+        assert_eq!(records.len(), 4);
+    }
+
+    #[test]
+    fn test_fix_signatures_resigns_rewritten_rrsets() {
+        let upstream = DnxResigner { signer: Arc::new(signing_key()) };
+        let algorithm = Algorithm::ECDSAP256SHA256;
+        let pkcs8 = KeyPair::generate_pkcs8(algorithm).unwrap();
+        let key_file = std::env::temp_dir().join(format!("dnx-resign-key-{}", std::process::id()));
+        fs::write(&key_file, &pkcs8).unwrap();
+        let local = DnxResigner::load(&DnxSigningKey {
+            key_file: key_file.clone(),
+            algorithm,
+            signer_name: Some("lab.example".to_string()),
+        }).unwrap();
+        let local_key = local.signer.key().to_dnskey(algorithm).unwrap();
+
+        let mut records = signed_rrsets(&upstream);
+        let original = rrsig_covering(&records, RecordType::A).unwrap().clone();
+        let rewritten = rewrite(&mut records);
+
+        assert!(!fix_signatures(&mut records, &rewritten, Some(&local)));
+        let rrsig = rrsig_covering(&records, RecordType::A).unwrap();
+        assert_eq!(rrsig.signer_name(), &Name::from_str("lab.example.").unwrap());
+        assert_eq!(rrsig.sig_expiration(), original.sig_expiration());
+        assert_eq!(rrsig.sig_inception(), original.sig_inception());
+
+        let name = Name::from_str("www.lab.example.").unwrap();
+        let rrset: Vec<Record> = records.iter().filter(|record| record.record_type() == RecordType::A).cloned().collect();
+        local_key.verify_rrsig(&name, DNSClass::IN, rrsig, &rrset).unwrap();
+        assert!(upstream.signer.key().to_dnskey(algorithm).unwrap().verify_rrsig(&name, DNSClass::IN, &original, &rrset).is_err());
+
+        fs::remove_file(key_file).unwrap();
+    }
+
+    #[test]
+    fn test_nat_dnssec_config() {
+        let strip: DnxNatDnssec = serde_json::from_str(r#""strip""#).unwrap();
+        assert!(strip.is_strip());
+
+        let resign: DnxNatDnssec = serde_json::from_str(r#"{
+            "resign": { "key_file": "/etc/dnx/lab.pk8", "algorithm": "ECDSAP256SHA256" }
+        }"#).unwrap();
+        let DnxNatDnssec::Resign(key) = resign else {
+            panic!("expected a signing key");
+        };
+        assert_eq!(key.algorithm, Algorithm::ECDSAP256SHA256);
+        assert_eq!(key.signer_name, None);
+        assert!(DnxResigner::load(&key).is_err());
+    }
+}
@@ -0,0 +1,172 @@
+use std::net::IpAddr;
+
+use hickory_server::proto::{
+    op::Edns,
+    rr::rdata::opt::{ClientSubnet, EdnsCode, EdnsOption},
+};
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+
+/// What to tell a zone's upstream about the client's network, with EDNS
+/// Client Subnet (RFC 7871).
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DnxEcsMode {
+    /// Send no client subnet.
+    #[default]
+    Strip,
+    /// Forward the subnet the client sent, if any.
+    Passthrough,
+    /// Send the client's own address, truncated to the configured prefix.
+    Add,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct DnxEcsConfig {
+    pub mode: DnxEcsMode,
+    pub ipv4_prefix_len: u8,
+    pub ipv6_prefix_len: u8,
+}
+
+impl Default for DnxEcsConfig {
+    fn default() -> Self {
+        DnxEcsConfig {
+            mode: DnxEcsMode::Strip,
+            ipv4_prefix_len: 24,
+            ipv6_prefix_len: 56,
+        }
+    }
+}
+
+impl DnxEcsConfig {
+    /// The subnet to send upstream for a query from `client`, which carried
+    /// the subnet `requested`.
+    pub fn subnet(&self, client: IpAddr, requested: Option<IpNet>) -> Option<IpNet> {
+        match self.mode {
+            DnxEcsMode::Strip => None,
+            DnxEcsMode::Passthrough => requested,
+            DnxEcsMode::Add => {
+                let client = client.to_canonical();
+                let prefix_len = match client {
+                    IpAddr::V4(_) => self.ipv4_prefix_len,
+                    IpAddr::V6(_) => self.ipv6_prefix_len,
+                };
+                IpNet::new(client, prefix_len).ok().map(|net| net.trunc())
+            }
+        }
+    }
+}
+
+/// Decodes a client subnet option into its network and scope prefix length,
+/// as `ClientSubnet` doesn't expose its fields.
+fn decode(subnet: &ClientSubnet) -> Option<(IpNet, u8)> {
+    let bytes = Vec::<u8>::try_from(subnet).ok()?;
+    let (family, source_prefix, scope_prefix, address) = match bytes.as_slice() {
+        [0, family, source_prefix, scope_prefix, address @ ..] => (*family, *source_prefix, *scope_prefix, address),
+        _ => return None,
+    };
+
+    let address = match family {
+        1 => {
+            let mut octets = [0; 4];
+            octets.get_mut(..address.len())?.copy_from_slice(address);
+            IpAddr::from(octets)
+        }
+        2 => {
+            let mut octets = [0; 16];
+            octets.get_mut(..address.len())?.copy_from_slice(address);
+            IpAddr::from(octets)
+        }
+        _ => return None,
+    };
+
+    let net = IpNet::new(address, source_prefix).ok()?.trunc();
+    Some((net, scope_prefix))
+}
+
+fn subnet_option(edns: Option<&Edns>) -> Option<(IpNet, u8)> {
+    match edns?.option(EdnsCode::Subnet)? {
+        EdnsOption::Subnet(subnet) => decode(subnet),
+        _ => None,
+    }
+}
+
+/// The subnet a client sent with its query.
+pub fn client_subnet(edns: Option<&Edns>) -> Option<IpNet> {
+    subnet_option(edns).map(|(net, _)| net)
+}
+
+/// The network an upstream's answer to a query for `subnet` applies to,
+/// from the scope prefix length of its response. `None` means the answer
+/// applies to every client.
+pub fn response_scope(edns: Option<&Edns>, subnet: IpNet) -> Option<IpNet> {
+    let (_, scope_prefix) = subnet_option(edns)?;
+    if scope_prefix == 0 {
+        return None;
+    }
+
+    let prefix_len = scope_prefix.min(subnet.prefix_len());
+    IpNet::new(subnet.addr(), prefix_len).ok().map(|net| net.trunc())
+}
+
+/// The option carrying `subnet` in a query.
+pub fn to_option(subnet: IpNet) -> EdnsOption {
+    EdnsOption::Subnet(ClientSubnet::new(subnet.addr(), subnet.prefix_len(), 0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edns(subnet: ClientSubnet) -> Edns {
+        let mut edns = Edns::new();
+        edns.options_mut().insert(EdnsOption::Subnet(subnet));
+        edns
+    }
+
+    #[test]
+    fn test_subnet_by_mode() {
+        let client: IpAddr = "192.0.2.77".parse().unwrap();
+        let requested: IpNet = "198.51.100.0/24".parse().unwrap();
+        let mut config = DnxEcsConfig::default();
+
+        assert_eq!(config.subnet(client, Some(requested)), None);
+
+        config.mode = DnxEcsMode::Passthrough;
+        assert_eq!(config.subnet(client, Some(requested)), Some(requested));
+        assert_eq!(config.subnet(client, None), None);
+
+        config.mode = DnxEcsMode::Add;
+        config.ipv4_prefix_len = 20;
+        assert_eq!(config.subnet(client, Some(requested)), Some("192.0.0.0/20".parse().unwrap()));
+        assert_eq!(config.subnet("2001:db8:1:2::5".parse().unwrap(), None), Some("2001:db8:1::/56".parse().unwrap()));
+        assert_eq!(config.subnet("::ffff:192.0.2.77".parse().unwrap(), None), Some("192.0.0.0/20".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_client_subnet_round_trip() {
+        let subnet: IpNet = "192.0.2.0/24".parse().unwrap();
+        let mut edns = Edns::new();
+        edns.options_mut().insert(to_option(subnet));
+
+        assert_eq!(client_subnet(Some(&edns)), Some(subnet));
+        assert_eq!(client_subnet(Some(&Edns::new())), None);
+        assert_eq!(client_subnet(None), None);
+
+        let subnet: IpNet = "2001:db8:abcd::/48".parse().unwrap();
+        edns.options_mut().insert(to_option(subnet));
+        assert_eq!(client_subnet(Some(&edns)), Some(subnet));
+    }
+
+    #[test]
+    fn test_response_scope() {
+        let subnet: IpNet = "192.0.2.0/24".parse().unwrap();
+        let response = |scope| edns(ClientSubnet::new("192.0.2.0".parse().unwrap(), 24, scope));
+
+        assert_eq!(response_scope(Some(&response(16)), subnet), Some("192.0.0.0/16".parse().unwrap()));
+        assert_eq!(response_scope(Some(&response(32)), subnet), Some(subnet));
+        assert_eq!(response_scope(Some(&response(0)), subnet), None);
+        assert_eq!(response_scope(None, subnet), None);
+    }
+}
@@ -1,2 +1,19 @@
+/// Upper bound on the names, clients and responses tracked in memory, so a
+/// flood of distinct ones can't grow it without limit.
+pub(crate) const MAX_TRACKED: usize = 65536;
+
+pub mod acl;
+pub mod authority;
+pub mod blocklist;
+pub mod cache;
+pub mod coalesce;
+pub mod dnssec;
+pub mod ecs;
+pub mod metrics;
+pub mod ratelimit;
+pub mod records;
+pub mod rules;
 pub mod server;
-pub mod tree;
\ No newline at end of file
+pub mod tree;
+pub mod upstream;
+pub mod watch;
\ No newline at end of file
@@ -0,0 +1,61 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+/// Counters shared by the request handler, periodically written to the log.
+#[derive(Debug, Default)]
+pub struct DnxMetrics {
+    pub queries_rate_limited: AtomicU64,
+    pub responses_rate_limited: AtomicU64,
+    pub responses_slipped: AtomicU64,
+    pub queries_blocked: AtomicU64,
+    pub stale_answers_served: AtomicU64,
+    pub answers_prefetched: AtomicU64,
+    pub queries_coalesced: AtomicU64,
+}
+
+impl DnxMetrics {
+    pub fn increment(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn counters(&self) -> Vec<(&'static str, u64)> {
+        vec![
+            ("queries_rate_limited", self.queries_rate_limited.load(Ordering::Relaxed)),
+            ("responses_rate_limited", self.responses_rate_limited.load(Ordering::Relaxed)),
+            ("responses_slipped", self.responses_slipped.load(Ordering::Relaxed)),
+            ("queries_blocked", self.queries_blocked.load(Ordering::Relaxed)),
+            ("stale_answers_served", self.stale_answers_served.load(Ordering::Relaxed)),
+            ("answers_prefetched", self.answers_prefetched.load(Ordering::Relaxed)),
+            ("queries_coalesced", self.queries_coalesced.load(Ordering::Relaxed)),
+        ]
+    }
+}
+
+/// Logs the counters every `interval`, skipping intervals where nothing changed.
+pub fn spawn_logger(metrics: Arc<DnxMetrics>, interval: Duration) {
+    tokio::spawn(async move {
+        let mut last = metrics.counters();
+        let mut ticker = tokio::time::interval(interval);
+        ticker.tick().await;
+
+        loop {
+            ticker.tick().await;
+            let counters = metrics.counters();
+            if counters == last {
+                continue;
+            }
+
+            let line = counters.iter()
+                .map(|(name, value)| format!("{name}={value}"))
+                .collect::<Vec<_>>()
+                .join(" ");
+            log::info!("Counters: {line}");
+            last = counters;
+        }
+    });
+}
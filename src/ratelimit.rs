@@ -0,0 +1,396 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    hash::Hash,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use hickory_server::proto::{
+    op::ResponseCode,
+    rr::{LowerName, RecordType},
+};
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+
+use crate::{metrics::DnxMetrics, MAX_TRACKED};
+
+/// Full buckets are swept at most this often, as sweeping scans them all.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Token-bucket limits on queries per client and on identical responses
+/// (RRL). Clients are grouped by network prefix; a rate of 0 disables the
+/// corresponding limit.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct DnxRateLimitConfig {
+    pub queries_per_second: u32,
+    pub query_burst: u32,
+    pub responses_per_second: u32,
+    pub response_burst: u32,
+    /// Every `slip`th limited UDP response is answered with an empty truncated
+    /// response instead of being dropped, so genuine clients retry over TCP.
+    /// 0 drops every limited response.
+    pub slip: u32,
+    pub ipv4_prefix_len: u8,
+    pub ipv6_prefix_len: u8,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub exempt: Vec<IpNet>,
+}
+
+impl Default for DnxRateLimitConfig {
+    fn default() -> Self {
+        DnxRateLimitConfig {
+            queries_per_second: 0,
+            query_burst: 0,
+            responses_per_second: 0,
+            response_burst: 0,
+            slip: 2,
+            ipv4_prefix_len: 24,
+            ipv6_prefix_len: 56,
+            exempt: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DnxRateLimitVerdict {
+    Allow,
+    Drop,
+    Slip,
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+    limited: u64,
+}
+
+impl TokenBucket {
+    fn new(burst: f64, now: Instant) -> Self {
+        TokenBucket {
+            tokens: burst,
+            updated: now,
+            limited: 0,
+        }
+    }
+
+    fn refill(&mut self, rate: f64, burst: f64, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(burst);
+        self.updated = now;
+    }
+
+    fn take(&mut self, rate: f64, burst: f64, now: Instant) -> bool {
+        self.refill(rate, burst, now);
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            self.limited = 0;
+            true
+        } else {
+            self.limited += 1;
+            false
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct ResponseKey {
+    client: IpNet,
+    name: LowerName,
+    query_type: RecordType,
+    response_code: ResponseCode,
+}
+
+#[derive(Debug)]
+struct Buckets<K> {
+    buckets: HashMap<K, TokenBucket>,
+    /// Keys in the order their buckets were created, oldest first.
+    created: VecDeque<K>,
+    swept: Option<Instant>,
+}
+
+#[derive(Debug)]
+struct Limit<K> {
+    rate: f64,
+    burst: f64,
+    capacity: usize,
+    buckets: Mutex<Buckets<K>>,
+}
+
+impl<K: Eq + Hash + Clone> Limit<K> {
+    fn new(rate: u32, burst: u32) -> Option<Self> {
+        if rate == 0 {
+            return None;
+        }
+
+        Some(Limit {
+            rate: rate.into(),
+            burst: burst.max(1).into(),
+            capacity: MAX_TRACKED,
+            buckets: Mutex::new(Buckets {
+                buckets: HashMap::new(),
+                created: VecDeque::new(),
+                swept: None,
+            }),
+        })
+    }
+
+    /// Makes room for a new bucket. Full buckets, which limit nothing, are
+    /// swept at most every `SWEEP_INTERVAL`; if that frees nothing, the
+    /// oldest bucket is evicted.
+    fn make_room(&self, buckets: &mut Buckets<K>, now: Instant) {
+        let due = buckets.swept.is_none_or(|swept| now.saturating_duration_since(swept) >= SWEEP_INTERVAL);
+        if due {
+            buckets.buckets.retain(|_, bucket| {
+                bucket.refill(self.rate, self.burst, now);
+                bucket.tokens < self.burst
+            });
+            let Buckets { buckets: tracked, created, .. } = buckets;
+            created.retain(|key| tracked.contains_key(key));
+            buckets.swept = Some(now);
+        }
+
+        while buckets.buckets.len() >= self.capacity {
+            let Some(oldest) = buckets.created.pop_front() else {
+                break;
+            };
+            buckets.buckets.remove(&oldest);
+        }
+    }
+
+    /// Takes a token for `key`, returning how many requests in a row have been
+    /// limited, or `None` if the request is within its limit.
+    fn check(&self, key: K, now: Instant) -> Option<u64> {
+        let mut buckets = self.buckets.lock().unwrap();
+
+        if !buckets.buckets.contains_key(&key) {
+            if buckets.buckets.len() >= self.capacity {
+                self.make_room(&mut buckets, now);
+            }
+            buckets.created.push_back(key.clone());
+        }
+
+        let bucket = buckets.buckets.entry(key).or_insert_with(|| TokenBucket::new(self.burst, now));
+        if bucket.take(self.rate, self.burst, now) {
+            None
+        } else {
+            Some(bucket.limited)
+        }
+    }
+}
+
+pub struct DnxRateLimiter {
+    config: DnxRateLimitConfig,
+    queries: Option<Limit<IpNet>>,
+    responses: Option<Limit<ResponseKey>>,
+    metrics: Arc<DnxMetrics>,
+}
+
+impl DnxRateLimiter {
+    pub fn new(config: DnxRateLimitConfig, metrics: Arc<DnxMetrics>) -> Self {
+        DnxRateLimiter {
+            queries: Limit::new(config.queries_per_second, config.query_burst),
+            responses: Limit::new(config.responses_per_second, config.response_burst),
+            config,
+            metrics,
+        }
+    }
+
+    fn client_prefix(&self, client: IpAddr) -> Option<IpNet> {
+        let client = client.to_canonical();
+        if self.config.exempt.iter().any(|net| net.contains(&client)) {
+            return None;
+        }
+
+        let prefix_len = match client {
+            IpAddr::V4(_) => self.config.ipv4_prefix_len.min(32),
+            IpAddr::V6(_) => self.config.ipv6_prefix_len.min(128),
+        };
+
+        IpNet::new(client, prefix_len).ok().map(|net| net.trunc())
+    }
+
+    fn verdict(&self, limited: u64) -> DnxRateLimitVerdict {
+        if self.config.slip > 0 && limited.is_multiple_of(self.config.slip.into()) {
+            DnxRateLimitVerdict::Slip
+        } else {
+            DnxRateLimitVerdict::Drop
+        }
+    }
+
+    /// Applies the per-client query limit to an incoming request.
+    pub fn check_query(&self, client: IpAddr, now: Instant) -> DnxRateLimitVerdict {
+        let (Some(queries), Some(prefix)) = (&self.queries, self.client_prefix(client)) else {
+            return DnxRateLimitVerdict::Allow;
+        };
+
+        match queries.check(prefix, now) {
+            None => DnxRateLimitVerdict::Allow,
+            Some(limited) => {
+                if limited == 1 {
+                    log::debug!("Rate limiting queries from {prefix}");
+                }
+                DnxMetrics::increment(&self.metrics.queries_rate_limited);
+                self.verdict(limited)
+            }
+        }
+    }
+
+    /// Applies the identical-response limit to an outgoing response.
+    pub fn check_response(
+        &self,
+        client: IpAddr,
+        name: &LowerName,
+        query_type: RecordType,
+        response_code: ResponseCode,
+        now: Instant,
+    ) -> DnxRateLimitVerdict {
+        let (Some(responses), Some(prefix)) = (&self.responses, self.client_prefix(client)) else {
+            return DnxRateLimitVerdict::Allow;
+        };
+
+        let key = ResponseKey {
+            client: prefix,
+            name: name.clone(),
+            query_type,
+            response_code,
+        };
+
+        match responses.check(key, now) {
+            None => DnxRateLimitVerdict::Allow,
+            Some(limited) => {
+                if limited == 1 {
+                    log::debug!("Rate limiting {query_type} {response_code} responses for {name} to {prefix}");
+                }
+
+                let verdict = self.verdict(limited);
+                match verdict {
+                    DnxRateLimitVerdict::Slip => DnxMetrics::increment(&self.metrics.responses_slipped),
+                    _ => DnxMetrics::increment(&self.metrics.responses_rate_limited),
+                }
+                verdict
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{str::FromStr, sync::atomic::Ordering, time::Duration};
+
+    use hickory_server::proto::rr::Name;
+
+    use super::*;
+
+    fn limiter(config: DnxRateLimitConfig) -> DnxRateLimiter {
+        DnxRateLimiter::new(config, Arc::new(DnxMetrics::default()))
+    }
+
+    #[test]
+    fn test_query_limit_refills() {
+        let limiter = limiter(DnxRateLimitConfig {
+            queries_per_second: 2,
+            query_burst: 2,
+            slip: 0,
+            ..Default::default()
+        });
+        let client = "192.168.1.10".parse().unwrap();
+        let now = Instant::now();
+
+        assert_eq!(limiter.check_query(client, now), DnxRateLimitVerdict::Allow);
+        assert_eq!(limiter.check_query(client, now), DnxRateLimitVerdict::Allow);
+        assert_eq!(limiter.check_query(client, now), DnxRateLimitVerdict::Drop);
+
+        let later = now + Duration::from_millis(500);
+        assert_eq!(limiter.check_query(client, later), DnxRateLimitVerdict::Allow);
+        assert_eq!(limiter.check_query(client, later), DnxRateLimitVerdict::Drop);
+
+        assert_eq!(limiter.metrics.queries_rate_limited.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn test_query_limit_groups_by_prefix() {
+        let limiter = limiter(DnxRateLimitConfig {
+            queries_per_second: 1,
+            query_burst: 1,
+            slip: 0,
+            ..Default::default()
+        });
+        let now = Instant::now();
+
+        assert_eq!(limiter.check_query("10.0.0.1".parse().unwrap(), now), DnxRateLimitVerdict::Allow);
+        assert_eq!(limiter.check_query("10.0.0.2".parse().unwrap(), now), DnxRateLimitVerdict::Drop);
+        assert_eq!(limiter.check_query("10.0.1.1".parse().unwrap(), now), DnxRateLimitVerdict::Allow);
+    }
+
+    #[test]
+    fn test_exempt_clients() {
+        let limiter = limiter(DnxRateLimitConfig {
+            queries_per_second: 1,
+            query_burst: 1,
+            exempt: vec!["10.0.0.0/8".parse().unwrap()],
+            ..Default::default()
+        });
+        let client = "10.1.1.1".parse().unwrap();
+        let now = Instant::now();
+
+        for _ in 0..10 {
+            assert_eq!(limiter.check_query(client, now), DnxRateLimitVerdict::Allow);
+        }
+    }
+
+    #[test]
+    fn test_response_limit_slips() {
+        let limiter = limiter(DnxRateLimitConfig {
+            responses_per_second: 1,
+            response_burst: 1,
+            slip: 2,
+            ..Default::default()
+        });
+        let client = "192.168.1.10".parse().unwrap();
+        let name = LowerName::from(Name::from_str("example.com.").unwrap());
+        let other = LowerName::from(Name::from_str("example.org.").unwrap());
+        let now = Instant::now();
+        let check = |name| limiter.check_response(client, name, RecordType::A, ResponseCode::NoError, now);
+
+        assert_eq!(check(&name), DnxRateLimitVerdict::Allow);
+        assert_eq!(check(&name), DnxRateLimitVerdict::Drop);
+        assert_eq!(check(&name), DnxRateLimitVerdict::Slip);
+        assert_eq!(check(&name), DnxRateLimitVerdict::Drop);
+        assert_eq!(check(&other), DnxRateLimitVerdict::Allow);
+
+        assert_eq!(limiter.metrics.responses_rate_limited.load(Ordering::Relaxed), 2);
+        assert_eq!(limiter.metrics.responses_slipped.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_limit_evicts_oldest_bucket_when_full() {
+        let mut limit = Limit::new(1, 1).unwrap();
+        limit.capacity = 2;
+        let now = Instant::now();
+
+        assert_eq!(limit.check("a", now), None);
+        assert_eq!(limit.check("b", now), None);
+        // nothing is full, so the oldest bucket makes room
+        assert_eq!(limit.check("c", now), None);
+        {
+            let buckets = limit.buckets.lock().unwrap();
+            assert!(!buckets.buckets.contains_key("a"));
+            assert_eq!(buckets.created, ["b", "c"]);
+        }
+
+        // within the sweep interval, evicting doesn't scan the buckets
+        assert_eq!(limit.check("d", now + Duration::from_millis(500)), None);
+        assert_eq!(limit.buckets.lock().unwrap().created, ["c", "d"]);
+
+        // once swept, buckets that refilled go first, however new
+        assert_eq!(limit.check("c", now + Duration::from_millis(500)), Some(1));
+        assert_eq!(limit.check("e", now + Duration::from_millis(1200)), None);
+        let buckets = limit.buckets.lock().unwrap();
+        assert_eq!(buckets.created, ["d", "e"]);
+        assert_eq!(buckets.buckets.len(), 2);
+    }
+}
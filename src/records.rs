@@ -0,0 +1,291 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    net::IpAddr,
+    path::Path,
+};
+
+use hickory_server::proto::{
+    rr::{rdata::PTR, LowerName, Name, RData, Record, RecordType},
+    serialize::txt::RDataParser,
+};
+use serde::{Deserialize, Serialize};
+
+const DEFAULT_TTL: u32 = 300;
+/// Longest chain of local CNAMEs followed in one answer.
+const MAX_CNAME_CHAIN: usize = 8;
+
+fn default_ttl() -> u32 {
+    DEFAULT_TTL
+}
+
+/// A record answered locally instead of being forwarded. `value` uses zone
+/// file syntax for the record's data, e.g. `10 5 389 dc01.example.com.` for
+/// an SRV record.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DnxRecord {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub record_type: RecordType,
+    pub value: String,
+    #[serde(default = "default_ttl")]
+    pub ttl: u32,
+}
+
+impl DnxRecord {
+    fn to_record(&self) -> Result<Record, String> {
+        let mut name = Name::from_utf8(&self.name).map_err(|e| e.to_string())?;
+        name.set_fqdn(true);
+        let rdata = RData::try_from_str(self.record_type, &self.value).map_err(|e| e.to_string())?;
+
+        Ok(Record::from_rdata(name, self.ttl, rdata))
+    }
+}
+
+/// Static records and hosts file entries, answered authoritatively.
+#[derive(Debug, Default)]
+pub struct DnxRecords {
+    records: HashMap<LowerName, Vec<Record>>,
+}
+
+impl DnxRecords {
+    pub fn new(records: &[DnxRecord], hosts_file: Option<&Path>) -> Self {
+        let mut local = DnxRecords::default();
+
+        if let Some(path) = hosts_file {
+            match fs::read_to_string(path) {
+                Ok(hosts) => local.add_hosts(&hosts),
+                Err(e) => log::error!("Failed to read hosts file {}: {}", path.display(), e),
+            }
+        }
+
+        // Configured records are added last and replace the hosts file's
+        // records of the same name and type.
+        let mut configured = HashSet::new();
+        for record in records {
+            match record.to_record() {
+                Ok(record) => {
+                    let key = (LowerName::new(record.name()), record.record_type());
+                    if !configured.contains(&key) {
+                        if let Some(records) = local.records.get_mut(&key.0) {
+                            records.retain(|existing| existing.record_type() != key.1);
+                        }
+                        configured.insert(key);
+                    }
+                    local.insert(record);
+                }
+                Err(e) => log::error!("Skipping invalid record {} {}: {}", record.name, record.record_type, e),
+            }
+        }
+
+        local
+    }
+
+    fn insert(&mut self, record: Record) {
+        let records = self.records.entry(LowerName::new(record.name())).or_default();
+
+        if !records.contains(&record) {
+            records.push(record);
+        }
+    }
+
+    /// Adds `A`/`AAAA` records for every name in /etc/hosts formatted `hosts`,
+    /// and a `PTR` record back to the first name of each line.
+    fn add_hosts(&mut self, hosts: &str) {
+        for line in hosts.lines() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut fields = line.split_whitespace();
+
+            let Some(ip) = fields.next() else {
+                continue;
+            };
+            let Ok(ip) = ip.parse::<IpAddr>() else {
+                log::warn!("Skipping hosts file line with invalid address: {}", line.trim());
+                continue;
+            };
+
+            let names: Vec<Name> = fields.filter_map(|name| {
+                let mut name = Name::from_utf8(name).ok()?;
+                name.set_fqdn(true);
+                Some(name)
+            }).collect();
+
+            for name in &names {
+                let rdata = match ip {
+                    IpAddr::V4(ip) => RData::A(ip.into()),
+                    IpAddr::V6(ip) => RData::AAAA(ip.into()),
+                };
+                self.insert(Record::from_rdata(name.clone(), DEFAULT_TTL, rdata));
+            }
+
+            if let Some(name) = names.first() {
+                let rdata = RData::PTR(PTR(name.clone()));
+                self.insert(Record::from_rdata(ip.into(), DEFAULT_TTL, rdata));
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Looks up the local answer for a query. `None` means the name isn't
+    /// local and should be forwarded; an empty answer means the name exists
+    /// but has no records of that type.
+    pub fn lookup(&self, name: &LowerName, query_type: RecordType) -> Option<Vec<Record>> {
+        let mut records = self.records.get(name)?;
+        let mut answers = Vec::new();
+        let mut visited = HashSet::from([name.clone()]);
+
+        loop {
+            let matching = records.iter()
+                .filter(|record| query_type == RecordType::ANY || record.record_type() == query_type);
+            let len = answers.len();
+            answers.extend(matching.cloned());
+            if answers.len() > len {
+                return Some(answers);
+            }
+
+            let Some(cname) = records.iter().find(|record| record.record_type() == RecordType::CNAME) else {
+                return Some(answers);
+            };
+            answers.push(cname.clone());
+
+            // Follow the alias when its target is local too, so clients get
+            // the final records in a single answer. Loops and overly long
+            // chains stop at the first repeated or excess name.
+            let Some(RData::CNAME(target)) = cname.data() else {
+                return Some(answers);
+            };
+            let target = LowerName::new(target);
+            if visited.len() >= MAX_CNAME_CHAIN || !visited.insert(target.clone()) {
+                log::warn!("Not following local CNAME chain for {} past {}", name, target);
+                return Some(answers);
+            }
+            match self.records.get(&target) {
+                Some(target_records) => records = target_records,
+                None => return Some(answers),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    fn name(name: &str) -> LowerName {
+        LowerName::from(Name::from_str(name).unwrap())
+    }
+
+    fn record(name: &str, record_type: RecordType, value: &str) -> DnxRecord {
+        DnxRecord {
+            name: name.to_string(),
+            record_type,
+            value: value.to_string(),
+            ttl: DEFAULT_TTL,
+        }
+    }
+
+    #[test]
+    fn test_static_records() {
+        let records = DnxRecords::new(&[
+            record("fs01.example.com.", RecordType::A, "10.0.0.5"),
+            record("fs01.example.com.", RecordType::TXT, "\"file server\""),
+            record("_ldap._tcp.example.com.", RecordType::SRV, "0 100 389 dc01.example.com."),
+            record("files.example.com.", RecordType::CNAME, "fs01.example.com."),
+            record("printer.example.com", RecordType::A, "10.0.0.9"),
+        ], None);
+
+        let answers = records.lookup(&name("FS01.example.com."), RecordType::A).unwrap();
+        assert_eq!(answers.len(), 1);
+        assert_eq!(answers[0].data(), Some(&RData::A("10.0.0.5".parse::<std::net::Ipv4Addr>().unwrap().into())));
+
+        let answers = records.lookup(&name("fs01.example.com."), RecordType::TXT).unwrap();
+        assert_eq!(answers[0].data().unwrap().to_string(), "file server");
+
+        let answers = records.lookup(&name("_ldap._tcp.example.com."), RecordType::SRV).unwrap();
+        assert_eq!(answers[0].record_type(), RecordType::SRV);
+
+        assert!(records.lookup(&name("fs01.example.com."), RecordType::AAAA).unwrap().is_empty());
+        assert!(records.lookup(&name("fs02.example.com."), RecordType::A).is_none());
+
+        // names are fully qualified, with or without the trailing dot
+        let answers = records.lookup(&name("printer.example.com."), RecordType::A).unwrap();
+        assert!(answers[0].name().is_fqdn());
+    }
+
+    #[test]
+    fn test_cname_is_followed() {
+        let records = DnxRecords::new(&[
+            record("fs01.example.com.", RecordType::A, "10.0.0.5"),
+            record("files.example.com.", RecordType::CNAME, "fs01.example.com."),
+        ], None);
+
+        let answers = records.lookup(&name("files.example.com."), RecordType::A).unwrap();
+        let types: Vec<RecordType> = answers.iter().map(|record| record.record_type()).collect();
+        assert_eq!(types, vec![RecordType::CNAME, RecordType::A]);
+    }
+
+    #[test]
+    fn test_cname_loops_and_long_chains_stop() {
+        let records = DnxRecords::new(&[
+            record("a.example.com.", RecordType::CNAME, "b.example.com."),
+            record("b.example.com.", RecordType::CNAME, "a.example.com."),
+        ], None);
+
+        let answers = records.lookup(&name("a.example.com."), RecordType::A).unwrap();
+        assert_eq!(answers.len(), 2);
+
+        let chain: Vec<DnxRecord> = (0..20)
+            .map(|i| record(&format!("c{i}.example.com."), RecordType::CNAME, &format!("c{}.example.com.", i + 1)))
+            .collect();
+        let records = DnxRecords::new(&chain, None);
+        let answers = records.lookup(&name("c0.example.com."), RecordType::A).unwrap();
+        assert_eq!(answers.len(), MAX_CNAME_CHAIN);
+    }
+
+    #[test]
+    fn test_configured_records_replace_hosts_file() {
+        let hosts = std::env::temp_dir().join(format!("dnx-records-hosts-{}", std::process::id()));
+        fs::write(&hosts, "10.0.0.7 nas.example.com
+fd00::7 nas.example.com
+").unwrap();
+        let records = DnxRecords::new(&[
+            record("nas.example.com.", RecordType::A, "10.0.0.8"),
+            record("nas.example.com.", RecordType::A, "10.0.0.9"),
+        ], Some(&hosts));
+        fs::remove_file(hosts).unwrap();
+
+        let addresses: Vec<String> = records.lookup(&name("nas.example.com."), RecordType::A).unwrap().iter()
+            .map(|record| record.data().unwrap().to_string())
+            .collect();
+        assert_eq!(addresses, ["10.0.0.8", "10.0.0.9"]);
+        // other types from the hosts file are kept
+        assert_eq!(records.lookup(&name("nas.example.com."), RecordType::AAAA).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_invalid_records_are_skipped() {
+        let records = DnxRecords::new(&[
+            record("bad.example.com.", RecordType::A, "not-an-address"),
+        ], None);
+
+        assert!(records.is_empty());
+    }
+
+    #[test]
+    fn test_hosts_file() {
+        let mut records = DnxRecords::default();
+        records.add_hosts("# comment\n10.0.0.7 nas.example.com nas-alias.example.com\n\nfd00::7 nas.example.com # v6\n");
+
+        assert_eq!(records.lookup(&name("nas.example.com."), RecordType::A).unwrap().len(), 1);
+        assert_eq!(records.lookup(&name("nas.example.com."), RecordType::AAAA).unwrap().len(), 1);
+        assert_eq!(records.lookup(&name("nas-alias.example.com."), RecordType::A).unwrap().len(), 1);
+
+        let ptr = records.lookup(&name("7.0.0.10.in-addr.arpa."), RecordType::PTR).unwrap();
+        assert_eq!(ptr[0].data(), Some(&RData::PTR(PTR(Name::from_str("nas.example.com.").unwrap()))));
+    }
+}
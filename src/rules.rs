@@ -0,0 +1,227 @@
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+
+/// A name pattern for routing rules. Both kinds match the whole query name,
+/// case-insensitively, including its trailing dot.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DnxPattern {
+    /// A regular expression, such as `host[0-9]+\.lab\.`.
+    Regex(String),
+    /// A glob where `*` matches any run of characters within a single label,
+    /// `?` a single character and `[...]` a character class, such as
+    /// `*-dc01.local.`. Classes take ranges, `!` negates them, and none
+    /// match a dot. Every other character matches itself, as does a `[`
+    /// that isn't closed. The trailing dot is optional.
+    Glob(String),
+}
+
+impl DnxPattern {
+    pub fn as_str(&self) -> &str {
+        match self {
+            DnxPattern::Regex(pattern) | DnxPattern::Glob(pattern) => pattern,
+        }
+    }
+
+    fn to_regex(&self) -> Result<Regex, regex::Error> {
+        let pattern = match self {
+            DnxPattern::Regex(pattern) => pattern.clone(),
+            DnxPattern::Glob(glob) => glob_to_regex(glob),
+        };
+
+        RegexBuilder::new(&format!("^(?:{pattern})$"))
+            .case_insensitive(true)
+            .build()
+    }
+}
+
+fn glob_to_regex(glob: &str) -> String {
+    let chars: Vec<char> = glob.chars().collect();
+    let mut regex = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' => regex.push_str("[^.]*"),
+            '?' => regex.push_str("[^.]"),
+            '[' => {
+                if let Some((class, len)) = class_to_regex(&chars[i + 1..]) {
+                    regex.push_str(&class);
+                    i += len + 1;
+                    continue;
+                }
+                regex.push_str(r"\[");
+            }
+            c => regex.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+        i += 1;
+    }
+
+    if !glob.ends_with('.') {
+        regex.push_str(r"\.");
+    }
+
+    regex
+}
+
+/// Translates the glob class `chars` starts, just past its `[`, to a regex
+/// class that never matches a dot. Returns it with the number of characters
+/// it took, its `]` included, or `None` if the class isn't closed. A `]`
+/// first in the class is a member, as is a `-` first or last.
+fn class_to_regex(chars: &[char]) -> Option<(String, usize)> {
+    let negated = matches!(chars.first(), Some('!'));
+    let start = usize::from(negated);
+    let escape = |c: char| if c.is_ascii_punctuation() { format!("\\{c}") } else { c.to_string() };
+
+    let mut members = String::new();
+    let mut i = start;
+    loop {
+        let c = *chars.get(i)?;
+        if c == ']' && i > start {
+            break;
+        }
+        match (chars.get(i + 1), chars.get(i + 2)) {
+            (Some('-'), Some(&end)) if end != ']' => {
+                members.push_str(&format!("{}-{}", escape(c), escape(end)));
+                i += 3;
+            }
+            _ => {
+                members.push_str(&escape(c));
+                i += 1;
+            }
+        }
+    }
+
+    let class = if negated {
+        format!("[^.{members}]")
+    } else {
+        format!("[[{members}]&&[^.]]")
+    };
+    Some((class, i + 1))
+}
+
+/// An ordered list of pattern rules; the first rule matching a name wins.
+#[derive(Debug)]
+pub struct DnxRules<T> {
+    rules: Vec<(Regex, T)>,
+}
+
+impl<T> Default for DnxRules<T> {
+    fn default() -> Self {
+        DnxRules { rules: Vec::new() }
+    }
+}
+
+impl<T> DnxRules<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, pattern: &DnxPattern, value: T) -> Result<(), regex::Error> {
+        self.rules.push((pattern.to_regex()?, value));
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// The values of every rule, in order.
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        self.rules.iter().map(|(_, value)| value)
+    }
+
+    /// Finds the first rule matching `name`, a fully qualified name.
+    pub fn find(&self, name: &str) -> Option<&T> {
+        self.rules.iter()
+            .find(|(regex, _)| regex.is_match(name))
+            .map(|(_, value)| value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(patterns: &[DnxPattern]) -> DnxRules<usize> {
+        let mut rules = DnxRules::new();
+        for (i, pattern) in patterns.iter().enumerate() {
+            rules.push(pattern, i).unwrap();
+        }
+        rules
+    }
+
+    #[test]
+    fn test_glob_matches_within_a_label() {
+        let rules = rules(&[DnxPattern::Glob("*-dc01.local".to_string())]);
+
+        assert_eq!(rules.find("corp-dc01.local."), Some(&0));
+        assert_eq!(rules.find("CORP-DC01.local."), Some(&0));
+        assert_eq!(rules.find("-dc01.local."), Some(&0));
+        assert_eq!(rules.find("a.corp-dc01.local."), None);
+        assert_eq!(rules.find("corp-dc01xlocal."), None);
+        assert_eq!(rules.find("corp-dc02.local."), None);
+    }
+
+    #[test]
+    fn test_glob_classes_and_single_characters() {
+        let rules = rules(&[
+            DnxPattern::Glob("host[0-9]?.lab.".to_string()),
+            DnxPattern::Glob("srv[!0-9].lab.".to_string()),
+            DnxPattern::Glob("db[-+.]1.lab.".to_string()),
+            DnxPattern::Glob("app[]\\]{2}.lab.".to_string()),
+        ]);
+
+        assert_eq!(rules.find("host1a.lab."), Some(&0));
+        assert_eq!(rules.find("host12.lab."), Some(&0));
+        assert_eq!(rules.find("hostx1.lab."), None);
+        assert_eq!(rules.find("host1.lab."), None);
+        assert_eq!(rules.find("host1.2.lab."), None);
+        assert_eq!(rules.find("srva.lab."), Some(&1));
+        assert_eq!(rules.find("srv1.lab."), None);
+        // classes stay within a label, negated or not
+        assert_eq!(rules.find("srv..lab."), None);
+        assert_eq!(rules.find("db-1.lab."), Some(&2));
+        assert_eq!(rules.find("db.1.lab."), None);
+        // regex syntax in a class matches itself
+        assert_eq!(rules.find("app]{2}.lab."), Some(&3));
+        assert_eq!(rules.find("app\\{2}.lab."), Some(&3));
+        assert_eq!(rules.find("app]].lab."), None);
+    }
+
+    #[test]
+    fn test_glob_literals() {
+        let rules = rules(&[DnxPattern::Glob("srv(1)[2.lab.".to_string())]);
+
+        // an unclosed class matches itself
+        assert_eq!(rules.find("srv(1)[2.lab."), Some(&0));
+        assert_eq!(rules.find("srv12.lab."), None);
+    }
+
+    #[test]
+    fn test_regex_is_anchored() {
+        let rules = rules(&[DnxPattern::Regex(r"host[0-9]+\.lab\.".to_string())]);
+
+        assert_eq!(rules.find("host42.lab."), Some(&0));
+        assert_eq!(rules.find("myhost42.lab."), None);
+        assert_eq!(rules.find("host42.lab.example."), None);
+    }
+
+    #[test]
+    fn test_first_match_wins() {
+        let rules = rules(&[
+            DnxPattern::Glob("db-*.lab.".to_string()),
+            DnxPattern::Regex(r".*\.lab\.".to_string()),
+        ]);
+
+        assert_eq!(rules.find("db-1.lab."), Some(&0));
+        assert_eq!(rules.find("web-1.lab."), Some(&1));
+    }
+
+    #[test]
+    fn test_invalid_regex() {
+        let mut rules = DnxRules::new();
+
+        assert!(rules.push(&DnxPattern::Regex("host[".to_string()), ()).is_err());
+        assert!(rules.is_empty());
+    }
+}
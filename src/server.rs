@@ -1,16 +1,35 @@
 use std::{
-    net::Ipv4Addr,
-    time::Duration,
+    net::{IpAddr, Ipv4Addr},
+    sync::Arc,
+    time::{Duration, Instant},
     error::Error,
     path::{Path, PathBuf},
     fs::{File, self},
     io,
-    collections::HashMap, env,
+    collections::{HashMap, HashSet},
 };
 
-use crate::tree::{
-    Tree,
-    TreeSortable,
+#[cfg(windows)]
+use std::env;
+
+use crate::{
+    acl::DnxAcl,
+    authority::{self, DnxAuthorities},
+    cache::{DnxCache, DnxCacheKey, DnxCachedAnswer, DnxPrefetchConfig},
+    coalesce::DnxCoalescer,
+    dnssec::{self, DnxDnssecConfig, DnxNatDnssec, DnxResigner, DnxTrustAnchor, DnxValidator},
+    ecs::{self, DnxEcsConfig, DnxEcsMode},
+    blocklist::{self, DnxBlockAction, DnxBlocklist, DnxBlocklists},
+    metrics::{self, DnxMetrics},
+    ratelimit::{DnxRateLimitConfig, DnxRateLimiter, DnxRateLimitVerdict},
+    records::{DnxRecord, DnxRecords},
+    rules::{DnxPattern, DnxRules},
+    tree::{
+        Label,
+        Tree,
+        TreeSortable,
+    },
+    upstream::{self, DnxNameServers, DnxRelayedResponse, DnxZoneTransfer},
 };
 
 use hickory_server::{
@@ -19,58 +38,319 @@ use hickory_server::{
         ResponseHandler,
         Request,
         ResponseInfo,
+        Protocol as ServerProtocol,
     },
     proto::op::{
+        Edns,
         Header,
+        Message,
         ResponseCode,
         OpCode,
+        Query,
     },
     ServerFuture,
-    authority::MessageResponseBuilder,
+    authority::{MessageResponse, MessageResponseBuilder},
 };
 
 use hickory_resolver::{
     TokioAsyncResolver, config::{
-        NameServerConfig, Protocol, ResolverConfig, ResolverOpts
-    }, error::{ResolveError, ResolveErrorKind}, proto::rr::{RData, Record, RecordType}
+        NameServerConfigGroup, ResolverConfig, ResolverOpts
+    }, error::{ResolveError, ResolveErrorKind}, name_server::{NameServerPool, TokioConnectionProvider}, proto::{
+        error::ProtoError,
+        rr::{LowerName, Name, RData, Record, RecordType},
+        xfer::{DnsHandle, DnsRequestOptions, FirstAnswer},
+    }
 };
 
 use tokio::{net::{
     UdpSocket,
     TcpListener,
-}, sync::RwLock};
+}, sync::Semaphore};
+
+use ipnet::IpNet;
 
 use serde::{
     de::DeserializeOwned,
     Deserialize,
+    Deserializer,
     Serialize,
 };
 
 const TCP_TIMEOUT: Duration = Duration::from_secs(10);
+const METRICS_INTERVAL: Duration = Duration::from_secs(60);
+const RELOAD_INTERVAL: Duration = Duration::from_secs(30);
+/// How long negative answers are cached for without a `negative_max_ttl`,
+/// the longest RFC 2308 recommends.
+const MAX_NEGATIVE_TTL: u32 = 3 * 60 * 60;
+/// RFC 2930 key negotiation, which hickory has no record type for.
+const TKEY: RecordType = RecordType::Unknown(249);
 
-pub struct DnxRequestHandler {
-    tree: Tree<String, DnxEntry>,
+/// The zone tree and default server used to route requests from one set of
+/// clients.
+struct DnxRoutes {
+    view: String,
+    match_clients: Vec<IpNet>,
+    rules: DnxRules<DnxEntry>,
+    tree: Tree<Label, DnxEntry>,
+    authorities: Arc<DnxAuthorities>,
     default_server: DnxEntry,
-    resolvers: RwLock<HashMap<String, TokioAsyncResolver>>,
+    /// The servers `default_server` forwards to.
+    default_servers: DnxNameServers,
+    /// Validates answers from the default server, when DNSSEC is enabled.
+    validator: Option<DnxValidator>,
 }
 
-impl DnxRequestHandler {
-    fn from_config(config: DnxConfig) -> Self{
+impl DnxRoutes {
+    fn new(
+        view: String,
+        match_clients: Vec<IpNet>,
+        zones: &[DnxEntry],
+        rules: &[DnxRule],
+        default_servers: &DnxNameServers,
+        options: &DnxResolverOptions,
+        trust_anchor: Option<&DnxTrustAnchor>,
+    ) -> Self {
+        let mut compiled = DnxRules::new();
+        rules.iter().for_each(|rule| {
+            let entry = rule.to_entry().prepared(options);
+            if let Err(e) = compiled.push(&rule.pattern, entry) {
+                log::error!("Skipping invalid rule {}: {}", rule.pattern.as_str(), e);
+            }
+        });
+
+        let mut configured = Tree::new();
+        zones.iter().for_each(|entry| {
+            if entry.zone.is_empty() {
+                log::error!("Skipping zone without a name");
+                return;
+            }
+            if entry.server.is_none() && entry.zone_file.is_none() {
+                log::error!("Skipping zone {} without a server or zone file", entry.zone);
+                return;
+            }
+            match entry.normalized() {
+                Ok(entry) => {
+                    configured.insert(entry);
+                }
+                Err(e) => log::error!("Skipping invalid zone {}: {}", entry.zone, e),
+            }
+        });
+        // an entry may only exclude its own subzones
+        let excluded: Vec<String> = zones.iter().flat_map(|entry| {
+            let parent = normalize_zone(&entry.zone).ok();
+            entry.exclude.iter().filter_map(move |zone| {
+                let excluded = normalize_zone(zone)
+                    .map_err(|e| log::error!("Skipping invalid excluded zone {}: {}", zone, e))
+                    .ok()?;
+                if !parent.as_deref().is_some_and(|parent| is_subzone(&excluded, parent)) {
+                    log::error!("Skipping excluded zone {} outside of zone {}", zone, entry.zone);
+                    return None;
+                }
+                Some(excluded)
+            })
+        }).collect();
+        excluded.iter().for_each(|zone| configured.exclude(zone.as_str()));
+
         let mut tree = Tree::new();
-        config.zones.iter().for_each(|entry| {
-            tree.insert(entry.clone());
+        configured.iter().for_each(|(_, entry)| {
+            let ancestors = configured.ancestors(entry.zone.as_str());
+            let ancestors = ancestors.iter().rev().map(|(_, ancestor)| *ancestor);
+            tree.insert(entry.inherited(ancestors).prepared(options));
+        });
+        excluded.iter().for_each(|zone| tree.exclude(zone.as_str()));
+
+        let mut authorities = DnxAuthorities::new();
+        tree.iter().for_each(|(_, entry)| {
+            if let Some(ref path) = entry.zone_file {
+                match entry.origin() {
+                    Ok(origin) => authorities.insert(origin, path.clone()),
+                    Err(e) => log::error!("Skipping zone file for invalid zone {}: {}", entry.zone, e),
+                }
+            }
         });
 
+        let validator = trust_anchor.and_then(|trust_anchor| match default_servers.to_configs() {
+            Ok(servers) => Some(DnxValidator::new(servers, options.to_resolver_opts(), trust_anchor.clone())),
+            Err(e) => {
+                log::error!("Not validating answers in view '{}', failed to configure the default server: {}", view, e);
+                None
+            }
+        });
+        let default_server = match default_servers {
+            DnxNameServers::Address(address) => Some(*address),
+            _ => None,
+        };
+
         Self {
+            view,
+            match_clients,
+            rules: compiled,
             tree,
+            authorities: Arc::new(authorities),
             default_server: DnxEntry {
                 zone: "".to_string(),
-                server: config.default_server,
+                server: default_server,
+                zone_file: None,
                 nat: None,
+                options: options.clone(),
+                acl: DnxAcl::default(),
+                exclude: Vec::new(),
+                query_types: None,
+                fallback: None,
+                reject_updates: false,
+                forward_notify: false,
+                transfer_allow: Vec::new(),
+                ecs: None,
             },
-            resolvers: RwLock::new(HashMap::new()),
+            default_servers: default_servers.clone(),
+            validator,
+        }
+    }
+
+    fn matches(&self, client: IpAddr) -> bool {
+        let client = client.to_canonical();
+        self.match_clients.iter().any(|net| net.contains(&client))
+    }
+
+    /// Rules are checked first, in order, and the first match wins. Names
+    /// matching no rule are routed by zone, then to the default server.
+    fn find(&self, name: &LowerName) -> &DnxEntry {
+        self.find_zone(name).unwrap_or(&self.default_server)
+    }
+
+    /// Like `find`, but without falling back to the default server.
+    fn find_zone(&self, name: &LowerName) -> Option<&DnxEntry> {
+        if !self.rules.is_empty() {
+            if let Some(entry) = self.rules.find(&name.to_string()) {
+                return Some(entry);
+            }
+        }
+
+        self.tree.find_name(name)
+    }
+
+    /// Every entry queries may be routed to, including fallbacks.
+    fn entries(&self) -> impl Iterator<Item = &DnxEntry> {
+        let entries = std::iter::once(&self.default_server)
+            .chain(self.tree.iter().map(|(_, entry)| entry))
+            .chain(self.rules.values());
+        entries.flat_map(|entry| std::iter::once(entry).chain(entry.fallback.as_deref()))
+    }
+
+    /// The upstream queries routed to `entry` are forwarded to.
+    fn upstream(&self, entry: &DnxEntry) -> Option<DnxUpstream> {
+        if !std::ptr::eq(entry, &self.default_server) {
+            return entry.upstream();
+        }
+
+        Some(DnxUpstream {
+            servers: self.default_servers.clone(),
+            options: entry.options.for_resolver(),
+        })
+    }
+
+    /// The validator for answers from `entry`. Only the default server's are
+    /// validated, as NAT rewrites break the signatures of zones.
+    fn validator_for(&self, entry: &DnxEntry) -> Option<&DnxValidator> {
+        self.validator.as_ref().filter(|_| std::ptr::eq(entry, &self.default_server))
+    }
+
+    /// Picks the entry serving `query_type` among `entry` and its fallback,
+    /// or the default server if neither does.
+    fn for_query_type<'a>(&'a self, entry: &'a DnxEntry, query_type: RecordType) -> &'a DnxEntry {
+        match entry.query_types {
+            Some(ref query_types) if !query_types.contains(&query_type) => {
+                entry.fallback.as_deref().unwrap_or(&self.default_server)
+            }
+            _ => entry,
+        }
+    }
+}
+
+pub struct DnxRequestHandler {
+    views: Vec<DnxRoutes>,
+    routes: DnxRoutes,
+    acl: DnxAcl,
+    records: DnxRecords,
+    blocklists: Arc<DnxBlocklists>,
+    rate_limiter: Option<DnxRateLimiter>,
+    metrics: Arc<DnxMetrics>,
+    resolvers: DnxResolverPool,
+    cache: Arc<DnxCache>,
+    /// Limits the prefetches running at once, if answers are prefetched.
+    prefetches: Option<Arc<Semaphore>>,
+    /// Resolver lookups in flight, by zone and query.
+    lookups: DnxCoalescer<(String, DnxCacheKey), Result<DnxCachedAnswer, ResolveError>>,
+}
+
+impl DnxRequestHandler {
+    fn from_config(config: DnxConfig) -> Self{
+        let trust_anchor = config.dnssec.as_ref().and_then(|dnssec| {
+            dnssec.trust_anchor()
+                .map_err(|e| log::error!("Disabling DNSSEC validation, failed to load trust anchors: {}", e))
+                .ok()
+        });
+
+        let views: Vec<DnxRoutes> = config.views.iter().map(|view| {
+            DnxRoutes::new(
+                view.name.clone(),
+                view.match_clients.clone(),
+                &view.zones,
+                &view.rules,
+                view.default_server.as_ref().unwrap_or(&config.default_server),
+                &config.options,
+                trust_anchor.as_ref(),
+            )
+        }).collect();
+
+        let routes = DnxRoutes::new(
+            "".to_string(),
+            Vec::new(),
+            &config.zones,
+            &config.rules,
+            &config.default_server,
+            &config.options,
+            trust_anchor.as_ref(),
+        );
+
+        let upstreams = views.iter().chain([&routes]).flat_map(|routes| {
+            routes.entries().filter_map(|entry| routes.upstream(entry))
+        });
+        let resolvers = DnxResolverPool::new(upstreams);
+
+        let (cache, prefetches) = match config.prefetch {
+            Some(prefetch) => (
+                DnxCache::with_prefetch(prefetch.min_hits),
+                Some(Arc::new(Semaphore::new(prefetch.concurrency))),
+            ),
+            None => (DnxCache::new(), None),
+        };
+
+        let metrics = Arc::new(DnxMetrics::default());
+        let rate_limiter = config.rate_limit.map(|rate_limit| {
+            DnxRateLimiter::new(rate_limit, metrics.clone())
+        });
+
+        Self {
+            views,
+            routes,
+            acl: config.acl,
+            records: DnxRecords::new(&config.records, config.hosts_file.as_deref()),
+            blocklists: Arc::new(DnxBlocklists::new(&config.blocklists)),
+            rate_limiter,
+            metrics,
+            resolvers,
+            cache: Arc::new(cache),
+            prefetches,
+            lookups: DnxCoalescer::new(),
         }
     }
+
+    fn routes_for(&self, client: IpAddr) -> &DnxRoutes {
+        self.views.iter()
+            .find(|view| view.matches(client))
+            .unwrap_or(&self.routes)
+    }
     async fn do_handle_request<R: ResponseHandler>(
         &self,
         request: &Request,
@@ -81,80 +361,725 @@ impl DnxRequestHandler {
 
         log::trace!("Handling request: {:?}", request);
 
+        let client = request.src().ip();
+        let routes = self.routes_for(client);
+        if !self.acl.permits(client) {
+            log::debug!("Refusing request from disallowed client: {}", client);
+            header.set_response_code(ResponseCode::Refused);
+            let response = builder.build(header, &[], &[], &[], &[]);
+            return Ok(self.send_response(request, response_handle, response).await?);
+        }
+
         Ok(match request.op_code() {
+            OpCode::Query if matches!(request.query().query_type(), RecordType::AXFR | RecordType::IXFR) => {
+                self.relay_transfer(request, response_handle, routes).await?
+            }
+            OpCode::Query if request.query().query_type() == TKEY => {
+                self.relay_to_zone(request, response_handle, routes).await?
+            }
             OpCode::Query => {
                 let query = request.query();
                 let name = query.name();
-                let entry = self.tree.find(name).unwrap_or(&self.default_server);
-                log::trace!("Found entry: {:?}", entry);
-                let resolver = self.get_resolver(entry).await;
-                log::trace!("Starting lookup for: {}", name);
-                let upstream_response = resolver.lookup(name, query.query_type()).await?;
-                log::trace!("Got upstream response: {:?}", upstream_response);
-                
+
+                if let Some(records) = self.records.lookup(name, query.query_type()) {
+                    log::trace!("Answering from local records: {:?}", records);
+                    header.set_authoritative(true);
+                    let response = builder.build(header, records.iter(), &[], &[], &[]);
+                    return Ok(self.send_response(request, response_handle, response).await?);
+                }
+
+                match self.blocklists.find(name) {
+                    None | Some(DnxBlockAction::Passthrough) => {}
+                    Some(action) => {
+                        log::debug!("Blocked query for {} from {}: {:?}", name, client, action);
+                        DnxMetrics::increment(&self.metrics.queries_blocked);
+                        let (rcode, records) = action.answer(name, query.query_type());
+                        header.set_response_code(rcode);
+                        let response = builder.build(header, records.iter(), &[], &[], &[]);
+                        return Ok(self.send_response(request, response_handle, response).await?);
+                    }
+                }
+
+                let entry = routes.find(name);
+                log::trace!("Found entry in view '{}': {:?}", routes.view, entry);
+
+                if !entry.acl.permits(client) {
+                    log::debug!("Refusing query for zone {} from disallowed client: {}", entry.zone, client);
+                    header.set_response_code(ResponseCode::Refused);
+                    let response = builder.build(header, &[], &[], &[], &[]);
+                    return Ok(self.send_response(request, response_handle, response).await?);
+                }
+
+                let entry = routes.for_query_type(entry, query.query_type());
+                if entry.zone_file.is_some() {
+                    let zone = LowerName::from(entry.origin()?);
+                    let Some(answer) = routes.authorities.lookup(&zone, request.request_info()).await else {
+                        return Err(format!("zone {} is not loaded", entry.zone).into());
+                    };
+                    log::trace!("Answering from zone file: {:?}", answer);
+                    header.set_authoritative(true);
+                    header.set_response_code(answer.response_code);
+                    let response = builder.build(
+                        header,
+                        answer.answers.iter(),
+                        answer.name_servers.iter(),
+                        answer.soa.iter(),
+                        answer.additionals.iter(),
+                    );
+                    return Ok(self.send_response(request, response_handle, response).await?);
+                }
+
+                // clients setting CD validate for themselves
+                let validator = routes.validator_for(entry).filter(|_| !request.header().checking_disabled());
+                if let Some(validator) = validator {
+                    let answer = self.lookup_validated(routes, validator, entry, query.original()).await?;
+                    // AD only goes to clients that show they understand it
+                    let wants_ad = request.header().authentic_data() || request.edns().is_some_and(|edns| edns.dnssec_ok());
+                    header.set_recursion_available(true);
+                    header.set_authentic_data(answer.authentic_data && wants_ad);
+                    header.set_response_code(answer.response_code);
+                    let response = builder.build(header, answer.answers.iter(), answer.name_servers.iter(), &[], &[]);
+                    return Ok(self.send_response(request, response_handle, response).await?);
+                }
+
+                // the resolver never asks for signatures, so clients wanting
+                // those of NAT zones, re-signed or not, query the upstream
+                let dnssec_ok = entry.nat().is_some() && request.edns().is_some_and(|edns| edns.dnssec_ok());
+                let ecs = entry.ecs.as_ref().filter(|ecs| ecs.mode != DnxEcsMode::Strip);
+                if ecs.is_some() || dnssec_ok {
+                    let subnet = ecs.and_then(|ecs| ecs.subnet(client, ecs::client_subnet(request.edns())));
+                    let answer = self.lookup_with_subnet(routes, entry, query.original(), subnet, dnssec_ok).await?;
+                    header.set_recursion_available(true);
+                    header.set_response_code(answer.response_code);
+                    let response = builder.build(header, answer.answers.iter(), answer.name_servers.iter(), &[], &[]);
+                    return Ok(self.send_response(request, response_handle, response).await?);
+                }
+
+                let key = DnxCacheKey {
+                    view: routes.view.clone(),
+                    name: name.clone(),
+                    query_type: query.query_type(),
+                    dnssec_ok: false,
+                };
+                let now = Instant::now();
+                if let Some(hit) = self.cache.get(&key, None, now) {
+                    log::trace!("Answering {} from cache", name);
+                    if hit.prefetch {
+                        self.prefetch(key, hit.scope, routes, entry, None);
+                    }
+                    header.set_recursion_available(true);
+                    header.set_response_code(hit.answer.response_code);
+                    let response = builder.build(header, hit.answer.answers.iter(), hit.answer.name_servers.iter(), &[], &[]);
+                    return Ok(self.send_response(request, response_handle, response).await?);
+                }
+
+                let resolver = self.resolver(routes, entry)?;
+                let lookup = fetch_resolved(resolver, entry, query.original());
+                let (result, coalesced) = self.lookups.run((entry.zone.clone(), key.clone()), lookup).await;
+                if coalesced {
+                    log::trace!("Shared a running lookup for {}", name);
+                    DnxMetrics::increment(&self.metrics.queries_coalesced);
+                }
+                let answer = match result {
+                    Ok(answer) => answer,
+                    Err(e) if rcode_from_error(&e) == ResponseCode::ServFail => {
+                        let Some(answer) = self.stale_answer(&key, None, false) else {
+                            return Err(e.into());
+                        };
+                        header.set_recursion_available(true);
+                        header.set_response_code(answer.response_code);
+                        let response = builder.build(header, answer.answers.iter(), answer.name_servers.iter(), &[], &[]);
+                        return Ok(self.send_response(request, response_handle, response).await?);
+                    }
+                    Err(e) => return Err(e.into()),
+                };
+
+                // the resolver caches answers itself, but can neither serve
+                // them stale nor refresh them ahead of expiry
+                cache_answer(&self.cache, key, None, &answer, &entry.options, now);
+
                 // Preserve upstream flags: recursion available and valid response
                 header.set_recursion_available(true);
-                
-                let records: Vec<Record> = upstream_response.record_iter().map(|record| {
-                    match record.record_type() {
-                        RecordType::A => {
-                            log::trace!("Translating A Record: {:?}", record);
-                            let parts = record.clone().into_parts();
-                            let rdata = parts.rdata.unwrap();
-                        
-                            let ip = if let RData::A(ip) = rdata {
-                                ip
-                            } else {
-                                panic!("Non A Record RData in A Record");
-                            };
-                        
-                            let ip = entry.translate(ip.into());
-                        
-                            let mut record = Record::new();
-                            record.set_record_type(parts.rr_type);
-                            record.set_data(Some(RData::A(ip.into())));
-                            record.set_dns_class(parts.dns_class);
-                            record.set_name(parts.name_labels);
-                            record.set_ttl(parts.ttl);
-
-                            record
-                        }
-                        _ => record.clone(),
-                    }
-                }).collect();
-                let response = builder.build(header, records.iter(), &[], &[], &[]);
+                header.set_response_code(answer.response_code);
+                let response = builder.build(header, answer.answers.iter(), answer.name_servers.iter(), &[], &[]);
                 log::trace!("Sending response: {:?}", response);
-                response_handle.send_response(response).await?
+                self.send_response(request, response_handle, response).await?
+            }
+            OpCode::Update | OpCode::Notify => {
+                self.relay_to_zone(request, response_handle, routes).await?
             }
             _ => {
                 header.set_response_code(ResponseCode::NotImp);
                 let response = builder.build(header, &[], &[], &[], &[]);
-                response_handle.send_response(response).await?
+                self.send_response(request, response_handle, response).await?
             }
         })
     }
-    async fn get_resolver(&self, entry: &DnxEntry) -> TokioAsyncResolver {
-        let resolver = {
-            self.resolvers.read().await.get(&entry.zone).cloned()
+
+    /// Relays an UPDATE, NOTIFY or TKEY query to the upstream of the zone it
+    /// names, and returns the upstream's response as received, signatures
+    /// included. Zones that aren't configured are answered NOTAUTH, and zones
+    /// that don't accept the message REFUSED or NOTIMP. TKEY negotiates keys
+    /// for updates, so it's refused along with them.
+    async fn relay_to_zone<R: ResponseHandler>(
+        &self,
+        request: &Request,
+        response_handle: &mut R,
+        routes: &DnxRoutes,
+    ) -> Result<ResponseInfo, Box<dyn Error + Send + Sync>> {
+        let builder = MessageResponseBuilder::from_message_request(request);
+        let mut header = Header::response_from_request(request.header());
+
+        let client = request.src().ip();
+        let zone = request.query().name();
+        let op_code = request.op_code();
+        let update = op_code == OpCode::Update || request.query().query_type() == TKEY;
+
+        let rcode = match routes.find_zone(zone) {
+            None => Err(ResponseCode::NotAuth),
+            Some(entry) if !entry.acl.permits(client) => Err(ResponseCode::Refused),
+            Some(entry) if update && entry.reject_updates => Err(ResponseCode::Refused),
+            Some(entry) if op_code == OpCode::Notify && !entry.forward_notify => Err(ResponseCode::NotImp),
+            Some(entry) => match entry.server {
+                Some(server) if entry.zone_file.is_none() => Ok((entry, server)),
+                _ => Err(ResponseCode::NotImp),
+            },
         };
-    
-        match resolver {
-            Some(resolver) => {
-                log::trace!("Using cached resolver for zone: {}", entry.zone);
-                resolver
+
+        let (entry, server) = match rcode {
+            Ok(upstream) => upstream,
+            Err(rcode) => {
+                log::debug!("Answering {:?} for {} from {} with {:?}", op_code, zone, client, rcode);
+                header.set_response_code(rcode);
+                let response = builder.build(header, &[], &[], &[], &[]);
+                return Ok(self.send_response(request, response_handle, response).await?);
             }
-            None => {
-                log::debug!("Creating resolver for zone: {}", entry.zone);
-                let options = ResolverOpts::default();
+        };
 
-                let nameservers = vec![NameServerConfig::new((entry.server, 53).into(), Protocol::Udp)];
-                let config = ResolverConfig::from_parts(None, vec![], nameservers);
+        log::debug!("Relaying {:?} for {} from {} to {}", op_code, zone, client, server);
+        let timeout = entry.options.to_resolver_opts().timeout;
+        let upstream = upstream::exchange((server, 53).into(), &upstream::to_message(request), timeout).await?;
+        log::trace!("Got upstream response: {:?}", upstream);
 
-                let resolver = TokioAsyncResolver::tokio(config, options);
-                self.resolvers.write().await.entry(entry.zone.clone()).or_insert(resolver).clone()
+        let upstream = DnxRelayedResponse::from(upstream);
+        Ok(self.send_response(request, response_handle, upstream.to_response(request)).await?)
+    }
+
+    /// Relays an AXFR or IXFR from the zone's upstream over TCP, applying the
+    /// zone's NAT to every message. Only clients in both the zone's `acl` and
+    /// its `transfer_allow` list may transfer it. Each message keeps its TSIG
+    /// signature, which only verifies if NAT left its records unchanged. UDP
+    /// requests are answered truncated so clients retry over TCP.
+    async fn relay_transfer<R: ResponseHandler>(
+        &self,
+        request: &Request,
+        response_handle: &mut R,
+        routes: &DnxRoutes,
+    ) -> Result<ResponseInfo, Box<dyn Error + Send + Sync>> {
+        let builder = MessageResponseBuilder::from_message_request(request);
+        let mut header = Header::response_from_request(request.header());
+
+        let client = request.src().ip();
+        let zone = request.query().name();
+
+        let upstream = match routes.find_zone(zone) {
+            None => Err(ResponseCode::NotAuth),
+            Some(entry) if !entry.permits_transfer(client) => Err(ResponseCode::Refused),
+            Some(entry) => match entry.server {
+                Some(server) if entry.zone_file.is_none() => Ok((entry, server)),
+                _ => Err(ResponseCode::Refused),
+            },
+        };
+
+        let (entry, server) = match upstream {
+            Ok(upstream) => upstream,
+            Err(rcode) => {
+                log::debug!("Answering transfer of {} from {} with {:?}", zone, client, rcode);
+                header.set_response_code(rcode);
+                let response = builder.build(header, &[], &[], &[], &[]);
+                return Ok(self.send_response(request, response_handle, response).await?);
+            }
+        };
+
+        if matches!(request.protocol(), ServerProtocol::Udp) {
+            header.set_truncated(true);
+            let response = builder.build_no_records(header);
+            return Ok(self.send_response(request, response_handle, response).await?);
+        }
+
+        log::debug!("Relaying transfer of {} from {} to {}", zone, client, server);
+        let timeout = entry.options.to_resolver_opts().timeout;
+        let transfer = DnxZoneTransfer::start((server, 53).into(), &upstream::to_message(request), timeout).await?;
+        self.relay_transfer_messages(request, response_handle, entry, transfer).await
+    }
+
+    /// Sends the client every message of `transfer`, with `entry`'s NAT
+    /// applied to the answers.
+    async fn relay_transfer_messages<R: ResponseHandler>(
+        &self,
+        request: &Request,
+        response_handle: &mut R,
+        entry: &DnxEntry,
+        mut transfer: DnxZoneTransfer,
+    ) -> Result<ResponseInfo, Box<dyn Error + Send + Sync>> {
+        let mut info = None;
+        while let Some(mut message) = transfer.next().await? {
+            let (records, stripped) = entry.translate_records(&message.take_answers());
+            if stripped {
+                message.set_authentic_data(false);
+            }
+            message.insert_answers(records);
+
+            let message = DnxRelayedResponse::from(message);
+            info = Some(self.send_response(request, response_handle, message.to_response(request)).await?);
+        }
+
+        Ok(info.ok_or("upstream ended the transfer without a response")?)
+    }
+
+    /// Looks up `query` from the zone's upstream, sending `subnet` with EDNS
+    /// Client Subnet and asking for signatures if `dnssec_ok`, and caches the
+    /// answer per the scope it returns.
+    async fn lookup_with_subnet(
+        &self,
+        routes: &DnxRoutes,
+        entry: &DnxEntry,
+        query: &Query,
+        subnet: Option<IpNet>,
+        dnssec_ok: bool,
+    ) -> Result<DnxCachedAnswer, Box<dyn Error + Send + Sync>> {
+        let key = DnxCacheKey {
+            view: routes.view.clone(),
+            name: LowerName::new(query.name()),
+            query_type: query.query_type(),
+            dnssec_ok,
+        };
+        let now = Instant::now();
+        if let Some(hit) = self.cache.get(&key, subnet, now) {
+            log::trace!("Answering {} from cache for subnet {:?}", query.name(), subnet);
+            if hit.prefetch {
+                self.prefetch(key, hit.scope, routes, entry, None);
+            }
+            return Ok(hit.answer);
+        }
+
+        let (answer, scope) = match fetch_with_subnet(entry, query, subnet, dnssec_ok).await {
+            Ok((answer, scope)) if answer.response_code != ResponseCode::ServFail => (answer, scope),
+            result => match self.stale_answer(&key, subnet, false) {
+                Some(answer) => return Ok(answer),
+                None => result?,
+            },
+        };
+
+        cache_answer(&self.cache, key, scope, &answer, &entry.options, now);
+        Ok(answer)
+    }
+
+    /// Looks up `query` from the default server and validates the answer
+    /// with DNSSEC. Bogus answers are errors, which clients get as SERVFAIL.
+    async fn lookup_validated(
+        &self,
+        routes: &DnxRoutes,
+        validator: &DnxValidator,
+        entry: &DnxEntry,
+        query: &Query,
+    ) -> Result<DnxCachedAnswer, Box<dyn Error + Send + Sync>> {
+        let key = DnxCacheKey {
+            view: routes.view.clone(),
+            name: LowerName::new(query.name()),
+            query_type: query.query_type(),
+            dnssec_ok: false,
+        };
+        let now = Instant::now();
+        // answers cached for clients setting CD weren't validated
+        if let Some(hit) = self.cache.get(&key, None, now).filter(|hit| hit.answer.validated) {
+            log::trace!("Answering {} from validated cache", query.name());
+            if hit.prefetch {
+                self.prefetch(key, None, routes, entry, Some(validator));
             }
+            return Ok(hit.answer);
+        }
+
+        let answer = match fetch_validated(validator, query).await {
+            Ok(answer) => answer,
+            Err(e) if rcode_from_error(&e) == ResponseCode::ServFail => match self.stale_answer(&key, None, true) {
+                Some(answer) => return Ok(answer),
+                None => return Err(e.into()),
+            },
+            Err(e) => return Err(e.into()),
+        };
+
+        cache_answer(&self.cache, key, None, &answer, &entry.options, now);
+        Ok(answer)
+    }
+
+    /// Looks up the answer cached for `key` and `scope` again in the
+    /// background, the way it was looked up: through `validator`, from
+    /// `entry`'s server for ECS and signatures, or from the name servers of
+    /// the zone's resolver. The resolver itself would answer from its cache.
+    /// Nothing is looked up if the configured number of prefetches are
+    /// already running, and the answer may be prefetched again later.
+    fn prefetch(
+        &self,
+        key: DnxCacheKey,
+        scope: Option<IpNet>,
+        routes: &DnxRoutes,
+        entry: &DnxEntry,
+        validator: Option<&DnxValidator>,
+    ) {
+        let permit = self.prefetches.as_ref().and_then(|prefetches| prefetches.clone().try_acquire_owned().ok());
+        let Some(permit) = permit else {
+            log::debug!("Too many prefetches running, not refreshing {} {}", key.name, key.query_type);
+            self.cache.finish_prefetch(&key, scope);
+            return;
+        };
+
+        let direct = key.dnssec_ok || entry.ecs.as_ref().is_some_and(|ecs| ecs.mode != DnxEcsMode::Strip);
+        let name_servers = self.name_servers(routes, entry).ok().filter(|_| !direct).cloned();
+        let cache = self.cache.clone();
+        let metrics = self.metrics.clone();
+        let entry = entry.clone();
+        let validator = validator.cloned();
+        tokio::spawn(async move {
+            log::trace!("Prefetching {} {} for subnet {:?}", key.name, key.query_type, scope);
+            let query = Query::query(Name::from(&key.name), key.query_type);
+            let now = Instant::now();
+            let result = match (validator, name_servers) {
+                (Some(validator), _) => fetch_validated(&validator, &query).await
+                    .map(|answer| (answer, None))
+                    .map_err(Into::into),
+                (None, Some(name_servers)) => fetch_uncached(&name_servers, &entry, &query).await
+                    .map(|answer| (answer, None))
+                    .map_err(Into::into),
+                (None, None) => fetch_with_subnet(&entry, &query, scope, key.dnssec_ok).await,
+            };
+
+            match result {
+                Ok((answer, answer_scope)) => {
+                    DnxMetrics::increment(&metrics.answers_prefetched);
+                    cache_answer(&cache, key.clone(), answer_scope, &answer, &entry.options, now);
+                }
+                Err(e) => log::debug!("Failed to prefetch {} {}: {}", key.name, key.query_type, e),
+            }
+            // answers that weren't cached again stay prefetchable
+            cache.finish_prefetch(&key, scope);
+            drop(permit);
+        });
+    }
+
+    /// An expired answer to serve after the upstream failed to answer `key`
+    /// for a query about `subnet`. With `validated`, only answers that went
+    /// through DNSSEC validation are served.
+    fn stale_answer(&self, key: &DnxCacheKey, subnet: Option<IpNet>, validated: bool) -> Option<DnxCachedAnswer> {
+        let answer = self.cache.get_stale(key, subnet, Instant::now())
+            .filter(|answer| answer.validated || !validated)?;
+        log::debug!("Upstream failed, serving stale answer for {} {}", key.name, key.query_type);
+        DnxMetrics::increment(&self.metrics.stale_answers_served);
+        Some(answer)
+    }
+
+    /// Sends `response` unless response rate limiting decides to drop it or
+    /// slip it. Limits only apply to UDP, where the source can be spoofed.
+    async fn send_response<'a, R: ResponseHandler>(
+        &self,
+        request: &Request,
+        response_handle: &mut R,
+        response: MessageResponse<
+            '_,
+            'a,
+            impl Iterator<Item = &'a Record> + Send + 'a,
+            impl Iterator<Item = &'a Record> + Send + 'a,
+            impl Iterator<Item = &'a Record> + Send + 'a,
+            impl Iterator<Item = &'a Record> + Send + 'a,
+        >,
+    ) -> io::Result<ResponseInfo> {
+        let verdict = match &self.rate_limiter {
+            Some(limiter) if matches!(request.protocol(), ServerProtocol::Udp) => {
+                let query = request.query();
+                limiter.check_response(
+                    request.src().ip(),
+                    query.name(),
+                    query.query_type(),
+                    response.header().response_code(),
+                    Instant::now(),
+                )
+            }
+            _ => DnxRateLimitVerdict::Allow,
+        };
+
+        match verdict {
+            DnxRateLimitVerdict::Allow => response_handle.send_response(response).await,
+            verdict => self.send_limited(request, response_handle, verdict).await,
         }
     }
+
+    /// Answers a rate limited request. Dropped requests get no answer, slipped
+    /// ones an empty truncated response so genuine clients retry over TCP. TCP
+    /// clients are refused instead, as truncation means nothing to them.
+    async fn send_limited<R: ResponseHandler>(
+        &self,
+        request: &Request,
+        response_handle: &mut R,
+        verdict: DnxRateLimitVerdict,
+    ) -> io::Result<ResponseInfo> {
+        let builder = MessageResponseBuilder::from_message_request(request);
+        let mut header = Header::response_from_request(request.header());
+
+        if !matches!(request.protocol(), ServerProtocol::Udp) {
+            header.set_response_code(ResponseCode::Refused);
+        } else if verdict == DnxRateLimitVerdict::Slip {
+            header.set_truncated(true);
+        } else {
+            log::trace!("Dropping rate limited response to {}", request.src());
+            return Ok(header.into());
+        }
+
+        response_handle.send_response(builder.build_no_records(header)).await
+    }
+
+    pub fn metrics(&self) -> Arc<DnxMetrics> {
+        self.metrics.clone()
+    }
+
+    fn resolver(&self, routes: &DnxRoutes, entry: &DnxEntry) -> Result<&TokioAsyncResolver, Box<dyn Error + Send + Sync>> {
+        let resolver = routes.upstream(entry).and_then(|upstream| self.resolvers.get(&upstream));
+        Ok(resolver.ok_or_else(|| format!("no resolver for zone '{}'", entry.zone))?)
+    }
+
+    /// The name servers of `entry`'s resolver, to query past its cache.
+    fn name_servers(&self, routes: &DnxRoutes, entry: &DnxEntry) -> Result<&DnxNameServerPool, Box<dyn Error + Send + Sync>> {
+        let name_servers = routes.upstream(entry).and_then(|upstream| self.resolvers.name_servers(&upstream));
+        Ok(name_servers.ok_or_else(|| format!("no resolver for zone '{}'", entry.zone))?)
+    }
+}
+
+/// How a zone's queries are resolved upstream. Zones with the same upstream
+/// share a resolver, and its cache.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct DnxUpstream {
+    servers: DnxNameServers,
+    options: DnxResolverOptions,
+}
+
+type DnxNameServerPool = NameServerPool<TokioConnectionProvider>;
+
+/// A resolver for every upstream in the config, created up front so lookups
+/// never wait on or race to create one. Each comes with its name servers
+/// without the resolver's cache, for prefetches.
+#[derive(Default)]
+struct DnxResolverPool {
+    resolvers: HashMap<DnxUpstream, (TokioAsyncResolver, DnxNameServerPool)>,
+}
+
+impl DnxResolverPool {
+    /// Upstreams whose servers can't be configured get no resolver, and
+    /// queries for them fail.
+    fn new(upstreams: impl IntoIterator<Item = DnxUpstream>) -> Self {
+        let mut pool = DnxResolverPool::default();
+        for upstream in upstreams {
+            if pool.resolvers.contains_key(&upstream) {
+                continue;
+            }
+
+            log::debug!("Creating resolver for {:?}", upstream.servers);
+            let nameservers = match upstream.servers.to_configs() {
+                Ok(nameservers) => nameservers,
+                Err(e) => {
+                    log::error!("Skipping upstream {:?}: {}", upstream.servers, e);
+                    continue;
+                }
+            };
+            let options = upstream.options.to_resolver_opts();
+            let name_servers = NameServerPool::from_config(
+                NameServerConfigGroup::from(nameservers.clone()),
+                options.clone(),
+                TokioConnectionProvider::default(),
+            );
+            let config = ResolverConfig::from_parts(None, vec![], nameservers);
+            let resolver = TokioAsyncResolver::tokio(config, options);
+            pool.resolvers.insert(upstream, (resolver, name_servers));
+        }
+        pool
+    }
+
+    fn get(&self, upstream: &DnxUpstream) -> Option<&TokioAsyncResolver> {
+        self.resolvers.get(upstream).map(|(resolver, _)| resolver)
+    }
+
+    fn name_servers(&self, upstream: &DnxUpstream) -> Option<&DnxNameServerPool> {
+        self.resolvers.get(upstream).map(|(_, name_servers)| name_servers)
+    }
+}
+
+/// Looks up `query` with `entry`'s resolver, and applies the entry's NAT to
+/// the answer. Negative answers are answers too, so they can be cached.
+async fn fetch_resolved(
+    resolver: &TokioAsyncResolver,
+    entry: &DnxEntry,
+    query: &Query,
+) -> Result<DnxCachedAnswer, ResolveError> {
+    log::trace!("Starting lookup for: {}", query.name());
+    let lookup = match resolver.lookup(query.name().clone(), query.query_type()).await {
+        Ok(lookup) => lookup,
+        Err(e) => return negative_answer(&e).ok_or(e),
+    };
+    log::trace!("Got upstream response: {:?}", lookup);
+
+    // answers from here are never marked authentic, stripped or not
+    let (answers, _) = entry.translate_records(lookup.record_iter());
+    Ok(DnxCachedAnswer {
+        response_code: ResponseCode::NoError,
+        answers,
+        name_servers: Vec::new(),
+        authentic_data: false,
+        validated: false,
+    })
+}
+
+/// Looks up `query` from `name_servers` like `fetch_resolved`, but without
+/// going through the resolver's cache, so the answer is fresh.
+async fn fetch_uncached(
+    name_servers: &DnxNameServerPool,
+    entry: &DnxEntry,
+    query: &Query,
+) -> Result<DnxCachedAnswer, ResolveError> {
+    let mut options = DnsRequestOptions::default();
+    options.use_edns = entry.options.to_resolver_opts().edns0;
+    let response = match name_servers.lookup(query.clone(), options).first_answer().await {
+        Ok(response) => response,
+        Err(e) => return negative_answer(&e).ok_or(e),
+    };
+    log::trace!("Got upstream response: {:?}", response);
+
+    let (answers, _) = entry.translate_records(response.answers());
+    Ok(DnxCachedAnswer {
+        response_code: response.response_code(),
+        answers,
+        name_servers: Vec::new(),
+        authentic_data: false,
+        validated: false,
+    })
+}
+
+/// Looks up `query` from `entry`'s server, sending `subnet` with EDNS Client
+/// Subnet, and setting DO if `dnssec_ok`. The resolver can do neither, and
+/// has its own cache, so the upstream is queried directly. Returns the answer
+/// and the clients it applies to, from the scope the upstream returns.
+async fn fetch_with_subnet(
+    entry: &DnxEntry,
+    query: &Query,
+    subnet: Option<IpNet>,
+    dnssec_ok: bool,
+) -> Result<(DnxCachedAnswer, Option<IpNet>), Box<dyn Error + Send + Sync>> {
+    let server = entry.server.ok_or_else(|| format!("zone {} has no server", entry.zone))?;
+    let message = subnet_query(query, subnet, dnssec_ok);
+
+    log::trace!("Looking up {} from {} for subnet {:?}", query.name(), server, subnet);
+    let options = entry.options.to_resolver_opts();
+    let response = upstream::exchange_attempts((server, 53).into(), &message, options.timeout, options.attempts).await?;
+    log::trace!("Got upstream response: {:?}", response);
+
+    let scope = subnet.and_then(|subnet| ecs::response_scope(response.extensions().as_ref(), subnet));
+    Ok((translated_answer(entry, &response), scope))
+}
+
+/// The query `fetch_with_subnet` sends upstream.
+fn subnet_query(query: &Query, subnet: Option<IpNet>, dnssec_ok: bool) -> Message {
+    let mut message = upstream::query_message(query.clone());
+    let mut edns = Edns::new();
+    edns.set_max_payload(upstream::EDNS_PAYLOAD);
+    edns.set_dnssec_ok(dnssec_ok);
+    if let Some(subnet) = subnet {
+        edns.options_mut().insert(ecs::to_option(subnet));
+    }
+    message.set_edns(edns);
+    message
+}
+
+/// The answer in an upstream's `response`, with `entry`'s NAT applied.
+fn translated_answer(entry: &DnxEntry, response: &Message) -> DnxCachedAnswer {
+    // answers from here are never marked authentic, stripped or not
+    let (answers, _) = entry.translate_records(response.answers());
+    DnxCachedAnswer {
+        response_code: response.response_code(),
+        answers,
+        name_servers: response.name_servers().to_vec(),
+        authentic_data: false,
+        validated: false,
+    }
+}
+
+/// Looks up `query` through `validator`. Negative answers are answers too,
+/// like on the resolver path, so they can be cached.
+async fn fetch_validated(validator: &DnxValidator, query: &Query) -> Result<DnxCachedAnswer, ResolveError> {
+    log::trace!("Looking up and validating {}", query.name());
+    let validated = match validator.lookup(query.clone()).await {
+        Ok(validated) => validated,
+        Err(e) => match negative_answer(&e) {
+            Some(answer) => return Ok(DnxCachedAnswer { validated: true, ..answer }),
+            None => return Err(e),
+        },
+    };
+    log::trace!("Got validated response: {:?}", validated);
+
+    let answers = validated.message.answers().iter()
+        .filter(|record| record.record_type() != RecordType::RRSIG)
+        .cloned()
+        .collect();
+    Ok(DnxCachedAnswer {
+        response_code: validated.message.response_code(),
+        answers,
+        name_servers: Vec::new(),
+        authentic_data: validated.secure,
+        validated: true,
+    })
+}
+
+/// The negative answer a resolver error stands for, with the SOA record the
+/// upstream sent to tell how long it may be cached. Its TTL is bounded by
+/// its minimum field, as RFC 2308 says, for clients to cache it as long.
+fn negative_answer(error: &ResolveError) -> Option<DnxCachedAnswer> {
+    let ResolveErrorKind::NoRecordsFound { soa: Some(soa), response_code, .. } = error.kind() else {
+        return None;
+    };
+
+    let minimum = soa.data().map_or(u32::MAX, |soa| soa.minimum());
+    let mut soa = soa.as_ref().clone().into_record_of_rdata();
+    soa.set_ttl(soa.ttl().min(minimum));
+    Some(DnxCachedAnswer {
+        response_code: *response_code,
+        answers: Vec::new(),
+        name_servers: vec![soa],
+        authentic_data: false,
+        validated: false,
+    })
+}
+
+/// Caches an answer for its TTL, and for `max_stale_ttl` past that. The TTL
+/// of positive answers is bounded by the zone's `positive_min_ttl` and
+/// `positive_max_ttl`, and that of NXDOMAIN and NODATA answers by
+/// `negative_min_ttl` and `negative_max_ttl`, or `MAX_NEGATIVE_TTL`.
+fn cache_answer(cache: &DnxCache, key: DnxCacheKey, scope: Option<IpNet>, answer: &DnxCachedAnswer, options: &DnxResolverOptions, now: Instant) {
+    let Some(mut ttl) = answer.ttl() else {
+        return;
+    };
+
+    let (min, max) = match answer.response_code {
+        ResponseCode::NoError if !answer.answers.is_empty() => (options.positive_min_ttl, options.positive_max_ttl),
+        ResponseCode::NoError | ResponseCode::NXDomain => {
+            (options.negative_min_ttl, options.negative_max_ttl.or(Some(MAX_NEGATIVE_TTL)))
+        }
+        _ => return,
+    };
+    if let Some(min) = min {
+        ttl = ttl.max(min);
+    }
+    if let Some(max) = max {
+        ttl = ttl.min(max);
+    }
+    let stale = options.max_stale_ttl.unwrap_or(0);
+    let seconds = |ttl: u32| Duration::from_secs(ttl.into());
+    cache.insert(key, scope, answer.clone(), seconds(ttl), seconds(stale), now);
 }
 
 fn rcode_from_error(error: &ResolveError) -> ResponseCode {
@@ -167,6 +1092,16 @@ fn rcode_from_error(error: &ResolveError) -> ResponseCode {
 #[async_trait::async_trait]
 impl RequestHandler for DnxRequestHandler {
     async fn handle_request<R: ResponseHandler>(&self, request: &Request, mut response_handle: R) -> ResponseInfo {
+        if let Some(limiter) = &self.rate_limiter {
+            let verdict = limiter.check_query(request.src().ip(), Instant::now());
+            if verdict != DnxRateLimitVerdict::Allow {
+                return match self.send_limited(request, &mut response_handle, verdict).await {
+                    Ok(info) => info,
+                    Err(_) => Header::response_from_request(request.header()).into(),
+                };
+            }
+        }
+
         match self.do_handle_request(request, &mut response_handle).await {
             Ok(info) => info,
             Err(e) => {
@@ -187,7 +1122,7 @@ impl RequestHandler for DnxRequestHandler {
 
                 let response = builder.build(header, &[], &[], &[], &[]);
 
-                match response_handle.send_response(response).await {
+                match self.send_response(request, &mut response_handle, response).await {
                     Ok(info) => info,
                     Err(_) => header.into(),
                 }
@@ -201,30 +1136,186 @@ struct DnxNatEntry {
     ip_original: Ipv4Addr,
     ip_translation: Ipv4Addr,
     mask: Ipv4Addr,
+    /// What to do with the signatures of the records this rule rewrites.
+    #[serde(default, skip_serializing_if = "DnxNatDnssec::is_strip")]
+    dnssec: DnxNatDnssec,
+    /// The key of `dnssec`, once loaded.
+    #[serde(skip)]
+    resigner: Option<DnxResigner>,
+}
+
+/// Tuning for the upstream resolver of a zone. Unset fields fall back to the
+/// global options in `DnxConfig`, then to hickory's `ResolverOpts::default()`.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[serde(default)]
+struct DnxResolverOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timeout_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    attempts: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cache_size: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    preserve_intermediates: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    positive_min_ttl: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    positive_max_ttl: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    negative_min_ttl: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    negative_max_ttl: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    edns0: Option<bool>,
+    /// How long past their TTL answers are kept to serve when the upstream
+    /// fails, see RFC 8767.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_stale_ttl: Option<u32>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 struct DnxEntry {
+    #[serde(default)]
     zone: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    server: Option<Ipv4Addr>,
+    /// An RFC 1035 zone file served authoritatively instead of forwarding to
+    /// `server`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    zone_file: Option<PathBuf>,
+    /// `None` when left out, to be inherited, and `Some(None)` when set to
+    /// null, which turns NAT off.
+    #[serde(default, deserialize_with = "present", skip_serializing_if = "Option::is_none")]
+    nat: Option<Option<DnxNatEntry>>,
+    #[serde(default)]
+    options: DnxResolverOptions,
+    #[serde(flatten)]
+    acl: DnxAcl,
+    /// Subzones routed to the default server instead of this zone.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    exclude: Vec<String>,
+    /// Query types served by this entry. Other types go to `fallback`, or to
+    /// the default server without one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    query_types: Option<Vec<RecordType>>,
+    /// Upstream for query types outside `query_types`. Its zone is always
+    /// that of the entry it belongs to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    fallback: Option<Box<DnxEntry>>,
+    /// Refuses dynamic updates for the zone instead of relaying them to
+    /// `server`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    reject_updates: bool,
+    /// Relays NOTIFY messages for the zone to `server`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    forward_notify: bool,
+    /// Clients allowed to transfer the zone with AXFR or IXFR.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    transfer_allow: Vec<IpNet>,
+    /// What to tell `server` about the client's network with EDNS Client
+    /// Subnet.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ecs: Option<DnxEcsConfig>,
+}
+
+/// Routes names matching `pattern` like a zone, for naming conventions that
+/// a suffix can't express.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct DnxRule {
+    #[serde(flatten)]
+    pattern: DnxPattern,
     server: Ipv4Addr,
     nat: Option<DnxNatEntry>,
+    #[serde(default)]
+    options: DnxResolverOptions,
+    #[serde(flatten)]
+    acl: DnxAcl,
+}
+
+impl DnxRule {
+    fn to_entry(&self) -> DnxEntry {
+        DnxEntry {
+            zone: self.pattern.as_str().to_string(),
+            server: Some(self.server),
+            zone_file: None,
+            nat: Some(self.nat.clone()),
+            options: self.options.clone(),
+            acl: self.acl.clone(),
+            exclude: Vec::new(),
+            query_types: None,
+            fallback: None,
+            reject_updates: false,
+            forward_notify: false,
+            transfer_allow: Vec::new(),
+            ecs: None,
+        }
+    }
+}
+
+/// A split-horizon view: clients whose address falls in `match_clients` are
+/// routed through this view's zones instead of the top-level ones.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+struct DnxView {
+    name: String,
+    match_clients: Vec<IpNet>,
+    zones: Vec<DnxEntry>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    rules: Vec<DnxRule>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    default_server: Option<DnxNameServers>,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
 struct DnxConfig {
     pub zones: Vec<DnxEntry>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<DnxRule>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub views: Vec<DnxView>,
     pub tcp_port: u16,
     pub udp_port: u16,
-    pub default_server: Ipv4Addr,
+    pub default_server: DnxNameServers,
+    #[serde(default)]
+    pub options: DnxResolverOptions,
+    #[serde(flatten)]
+    pub acl: DnxAcl,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_limit: Option<DnxRateLimitConfig>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub records: Vec<DnxRecord>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hosts_file: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub blocklists: Vec<DnxBlocklist>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dnssec: Option<DnxDnssecConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefetch: Option<DnxPrefetchConfig>,
 }
 
-impl TreeSortable<String> for DnxEntry {
-    fn get_path(&self) -> Vec<String> {
+impl TreeSortable<Label> for DnxEntry {
+    fn get_path(&self) -> Vec<Label> {
         self.zone.get_path()
     }
 }
 
 impl DnxNatEntry {
+    /// Loads the key for re-signing rewritten records. Signatures are
+    /// stripped instead if it fails to load.
+    fn load_resigner(&mut self, zone: &str) {
+        let DnxNatDnssec::Resign(ref key) = self.dnssec else {
+            return;
+        };
+
+        match DnxResigner::load(key) {
+            Ok(resigner) => self.resigner = Some(resigner),
+            Err(e) => log::error!(
+                "Stripping signatures of zone {} instead of re-signing, failed to load key {}: {}",
+                zone, key.key_file.display(), e,
+            ),
+        }
+    }
+
     fn matches(&self, ip: Ipv4Addr) -> bool {
         let mask = u32::from(self.mask);
         let ip = u32::from(ip);
@@ -249,24 +1340,248 @@ impl DnxNatEntry {
     }
 }
 
+impl DnxResolverOptions {
+    fn merged(&self, fallback: &DnxResolverOptions) -> DnxResolverOptions {
+        DnxResolverOptions {
+            timeout_ms: self.timeout_ms.or(fallback.timeout_ms),
+            attempts: self.attempts.or(fallback.attempts),
+            cache_size: self.cache_size.or(fallback.cache_size),
+            preserve_intermediates: self.preserve_intermediates.or(fallback.preserve_intermediates),
+            positive_min_ttl: self.positive_min_ttl.or(fallback.positive_min_ttl),
+            positive_max_ttl: self.positive_max_ttl.or(fallback.positive_max_ttl),
+            negative_min_ttl: self.negative_min_ttl.or(fallback.negative_min_ttl),
+            negative_max_ttl: self.negative_max_ttl.or(fallback.negative_max_ttl),
+            edns0: self.edns0.or(fallback.edns0),
+            max_stale_ttl: self.max_stale_ttl.or(fallback.max_stale_ttl),
+        }
+    }
+
+    /// The options the resolver is created with, leaving out those applied by
+    /// DNX's own cache, so they don't keep zones from sharing a resolver.
+    fn for_resolver(&self) -> DnxResolverOptions {
+        DnxResolverOptions {
+            max_stale_ttl: None,
+            ..self.clone()
+        }
+    }
+
+    fn to_resolver_opts(&self) -> ResolverOpts {
+        let mut options = ResolverOpts::default();
+
+        if let Some(timeout_ms) = self.timeout_ms {
+            options.timeout = Duration::from_millis(timeout_ms);
+        }
+        if let Some(attempts) = self.attempts {
+            options.attempts = attempts;
+        }
+        if let Some(cache_size) = self.cache_size {
+            options.cache_size = cache_size;
+        }
+        if let Some(preserve_intermediates) = self.preserve_intermediates {
+            options.preserve_intermediates = preserve_intermediates;
+        }
+        if let Some(edns0) = self.edns0 {
+            options.edns0 = edns0;
+        }
+
+        let seconds = |ttl: Option<u32>| ttl.map(|ttl| Duration::from_secs(ttl.into()));
+        options.positive_min_ttl = seconds(self.positive_min_ttl).or(options.positive_min_ttl);
+        options.positive_max_ttl = seconds(self.positive_max_ttl).or(options.positive_max_ttl);
+        options.negative_min_ttl = seconds(self.negative_min_ttl).or(options.negative_min_ttl);
+        options.negative_max_ttl = seconds(self.negative_max_ttl).or(options.negative_max_ttl);
+
+        options
+    }
+}
+
+/// Deserializes a field that may be null as `Some`, so a field set to null
+/// can be told from one left out, which `#[serde(default)]` makes `None`.
+fn present<'de, T: Deserialize<'de>, D: Deserializer<'de>>(deserializer: D) -> Result<Option<T>, D::Error> {
+    T::deserialize(deserializer).map(Some)
+}
+
+/// Converts a configured zone name to the form `LowerName::to_string`
+/// yields: Unicode labels in punycode, lowercase, with a trailing dot. A
+/// leading `*.` is kept for wildcard zones.
+fn normalize_zone(zone: &str) -> Result<String, ProtoError> {
+    let (wildcard, zone) = match zone.strip_prefix("*.") {
+        Some(zone) => (true, zone),
+        None => (false, zone),
+    };
+
+    let mut name = Name::from_utf8(zone)?.to_lowercase();
+    name.set_fqdn(true);
+    if wildcard {
+        name = Name::from_ascii("*")?.append_domain(&name)?;
+    }
+
+    Ok(name.to_ascii())
+}
+
+/// Whether `zone` is strictly below `parent`, both normalized. The `*.` of
+/// wildcard zones is ignored.
+fn is_subzone(zone: &str, parent: &str) -> bool {
+    let name = |zone: &str| Name::from_ascii(zone.strip_prefix("*.").unwrap_or(zone)).ok();
+    match (name(zone), name(parent)) {
+        (Some(zone), Some(parent)) => zone != parent && parent.zone_of(&zone),
+        _ => false,
+    }
+}
+
 impl DnxEntry {
+    /// The upstream forwarded queries go to. Entries served from a zone
+    /// file have none.
+    fn upstream(&self) -> Option<DnxUpstream> {
+        match self.server {
+            Some(server) if self.zone_file.is_none() => Some(DnxUpstream {
+                servers: DnxNameServers::Address(server),
+                options: self.options.for_resolver(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// The zone's apex, without the `*.` of wildcard zones.
+    fn origin(&self) -> Result<Name, ProtoError> {
+        Name::from_ascii(self.zone.trim_start_matches("*."))
+    }
+
+    /// Normalizes the zone name of the entry, see `normalize_zone`. A NAT
+    /// signing key without a signer name is given the zone's.
+    fn normalized(&self) -> Result<DnxEntry, ProtoError> {
+        let mut entry = self.clone();
+        entry.zone = normalize_zone(&self.zone)?;
+        if let Some(Some(DnxNatEntry { dnssec: DnxNatDnssec::Resign(ref mut key), .. })) = entry.nat {
+            if key.signer_name.is_none() {
+                key.signer_name = Some(entry.zone.trim_start_matches("*.").to_string());
+            }
+        }
+        Ok(entry)
+    }
+
+    /// Fills the NAT, resolver options, ACL and ECS policy left unset from `ancestors`,
+    /// the zones this one is nested in, nearest first.
+    fn inherited<'a>(&self, ancestors: impl IntoIterator<Item = &'a DnxEntry>) -> DnxEntry {
+        let mut entry = self.clone();
+
+        for ancestor in ancestors {
+            if entry.nat.is_none() {
+                entry.nat = ancestor.nat.clone();
+            }
+            if entry.acl.is_unset() {
+                entry.acl = ancestor.acl.clone();
+            }
+            if entry.ecs.is_none() {
+                entry.ecs = ancestor.ecs.clone();
+            }
+            entry.options = entry.options.merged(&ancestor.options);
+        }
+
+        entry
+    }
+
+    /// Applies the global resolver options, and the entry's own settings to
+    /// its fallback, and loads the NAT signing key.
+    fn prepared(&self, options: &DnxResolverOptions) -> DnxEntry {
+        let mut entry = self.clone();
+        entry.options = entry.options.merged(options);
+        if let Some(Some(ref mut nat)) = entry.nat {
+            nat.load_resigner(&entry.zone);
+        }
+        entry.fallback = entry.fallback.filter(|fallback| {
+            if fallback.server.is_none() {
+                log::error!("Skipping fallback without a server for zone {}", entry.zone);
+            }
+            fallback.server.is_some()
+        }).map(|fallback| {
+            let mut fallback = fallback.prepared(&entry.options);
+            fallback.zone = entry.zone.clone();
+            fallback.zone_file = None;
+            fallback.query_types = None;
+            fallback.fallback = None;
+            Box::new(fallback)
+        });
+        entry
+    }
+
+    fn nat(&self) -> Option<&DnxNatEntry> {
+        self.nat.as_ref().and_then(Option::as_ref)
+    }
+
     fn translate(&self, ip: Ipv4Addr) -> Ipv4Addr {
-        match self.nat {
+        match self.nat() {
             None => ip,
-            Some(ref nat) => {
+            Some(nat) => {
                 nat.translate(ip)
             }
         }
     }
+
+    /// Applies the entry's NAT to an A record; other records are unchanged.
+    fn translate_record(&self, record: &Record) -> Record {
+        match record.data() {
+            Some(RData::A(ip)) if self.nat().is_some() => {
+                log::trace!("Translating A Record: {:?}", record);
+                let ip = self.translate((*ip).into());
+
+                let mut record = record.clone();
+                record.set_data(Some(RData::A(ip.into())));
+                record
+            }
+            _ => record.clone(),
+        }
+    }
+
+    /// Applies the entry's NAT to the records of an answer. The signatures of
+    /// the RRsets it rewrote are re-signed or removed, as the NAT rule says.
+    /// Returns the records, and whether any signature was removed.
+    fn translate_records<'a>(&self, records: impl IntoIterator<Item = &'a Record>) -> (Vec<Record>, bool) {
+        let mut rewritten = HashSet::new();
+        let mut translated: Vec<Record> = records.into_iter().map(|record| {
+            let translation = self.translate_record(record);
+            if translation != *record {
+                rewritten.insert((record.name().clone(), record.record_type()));
+            }
+            translation
+        }).collect();
+
+        let stripped = match self.nat() {
+            Some(nat) if !rewritten.is_empty() => {
+                dnssec::fix_signatures(&mut translated, &rewritten, nat.resigner.as_ref())
+            }
+            _ => false,
+        };
+        (translated, stripped)
+    }
+
+    /// Whether `client` may transfer the zone: it must pass the zone's `acl`
+    /// as well as be in `transfer_allow`.
+    fn permits_transfer(&self, client: IpAddr) -> bool {
+        if !self.acl.permits(client) {
+            return false;
+        }
+        let client = client.to_canonical();
+        self.transfer_allow.iter().any(|net| net.contains(&client))
+    }
 }
 
 impl Default for DnxConfig {
     fn default() -> Self {
         DnxConfig {
             zones: Vec::new(),
+            rules: Vec::new(),
+            views: Vec::new(),
             tcp_port: 53,
             udp_port: 53,
-            default_server: Ipv4Addr::new(1, 1, 1, 1),
+            default_server: DnxNameServers::Address(Ipv4Addr::new(1, 1, 1, 1)),
+            options: DnxResolverOptions::default(),
+            acl: DnxAcl::default(),
+            rate_limit: None,
+            records: Vec::new(),
+            hosts_file: None,
+            blocklists: Vec::new(),
+            dnssec: None,
+            prefetch: None,
         }
     }
 }
@@ -318,12 +1633,24 @@ fn load_config() -> DnxConfig {
         let mut config = DnxConfig::default();
         config.zones.push(DnxEntry{
             zone: "example.com.".to_string(),
-            server: Ipv4Addr::new(192, 168, 0, 1),
-            nat: Some(DnxNatEntry {
+            server: Some(Ipv4Addr::new(192, 168, 0, 1)),
+            zone_file: None,
+            nat: Some(Some(DnxNatEntry {
                 ip_original: Ipv4Addr::new(192, 168, 0, 0),
                 ip_translation: Ipv4Addr::new(10, 0, 0, 0),
                 mask: Ipv4Addr::new(255, 255, 0, 0),
-            }),
+                dnssec: DnxNatDnssec::Strip,
+                resigner: None,
+            })),
+            options: DnxResolverOptions::default(),
+            acl: DnxAcl::default(),
+            exclude: Vec::new(),
+            query_types: None,
+            fallback: None,
+            reject_updates: false,
+            forward_notify: false,
+            transfer_allow: Vec::new(),
+            ecs: None,
         });
         save_json(&config, &path).unwrap();
         config
@@ -335,7 +1662,14 @@ fn load_config() -> DnxConfig {
 pub async fn setup_server() -> io::Result<ServerFuture<DnxRequestHandler>> {
     let config = load_config();
 
-    let mut server = ServerFuture::new(DnxRequestHandler::from_config(config.clone()));
+    let handler = DnxRequestHandler::from_config(config.clone());
+    metrics::spawn_logger(handler.metrics(), METRICS_INTERVAL);
+    blocklist::spawn_reloader(handler.blocklists.clone(), RELOAD_INTERVAL);
+    for routes in handler.views.iter().chain([&handler.routes]) {
+        authority::spawn_reloader(routes.authorities.clone(), RELOAD_INTERVAL);
+    }
+
+    let mut server = ServerFuture::new(handler);
 
     let udp_socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, config.udp_port)).await.unwrap();
     let tcp_socket = TcpListener::bind((Ipv4Addr::UNSPECIFIED, config.tcp_port)).await.unwrap();
@@ -348,14 +1682,77 @@ pub async fn setup_server() -> io::Result<ServerFuture<DnxRequestHandler>> {
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Mutex;
+
+    use hickory_server::authority::MessageRequest;
+    use hickory_resolver::proto::{
+        op::MessageType,
+        rr::{
+            dnssec::{rdata::{DNSSECRData, RRSIG}, Algorithm},
+            rdata,
+        },
+        serialize::binary::{BinDecodable, BinEncoder},
+    };
+    use tokio::net::TcpListener;
+
     use super::*;
 
+    /// Collects the responses sent to a client.
+    #[derive(Clone, Default)]
+    struct Responses(Arc<Mutex<Vec<Message>>>);
+
+    #[async_trait::async_trait]
+    impl ResponseHandler for Responses {
+        async fn send_response<'a>(
+            &mut self,
+            response: MessageResponse<
+                '_,
+                'a,
+                impl Iterator<Item = &'a Record> + Send + 'a,
+                impl Iterator<Item = &'a Record> + Send + 'a,
+                impl Iterator<Item = &'a Record> + Send + 'a,
+                impl Iterator<Item = &'a Record> + Send + 'a,
+            >,
+        ) -> io::Result<ResponseInfo> {
+            let mut bytes = Vec::new();
+            let info = response.destructive_emit(&mut BinEncoder::new(&mut bytes))?;
+            self.0.lock().unwrap().push(Message::from_vec(&bytes)?);
+            Ok(info)
+        }
+    }
+
+    fn lower(name: &str) -> LowerName {
+        LowerName::from(Name::from_ascii(name).unwrap())
+    }
+
+    /// A handler for `config`, listening on the standard ports and
+    /// forwarding to 1.1.1.1 unless it says otherwise.
+    fn handler(mut config: serde_json::Value) -> DnxRequestHandler {
+        let fields = config.as_object_mut().unwrap();
+        fields.entry("tcp_port").or_insert(53.into());
+        fields.entry("udp_port").or_insert(53.into());
+        fields.entry("default_server").or_insert("1.1.1.1".into());
+        DnxRequestHandler::from_config(serde_json::from_value(config).unwrap())
+    }
+
+    /// The cache key of an A query in the default view.
+    fn key(name: &str) -> DnxCacheKey {
+        DnxCacheKey {
+            view: "".to_string(),
+            name: lower(name),
+            query_type: RecordType::A,
+            dnssec_ok: false,
+        }
+    }
+
     #[test]
     fn test_dnx_nat_entry_matches() {
         let nat_entry = DnxNatEntry {
             ip_original: Ipv4Addr::new(192, 168, 0, 0),
             ip_translation: Ipv4Addr::new(10, 0, 0, 0),
             mask: Ipv4Addr::new(255, 255, 0, 0),
+            dnssec: DnxNatDnssec::Strip,
+            resigner: None,
         };
 
         assert!(nat_entry.matches(Ipv4Addr::new(192, 168, 1, 1)));
@@ -368,6 +1765,8 @@ mod tests {
             ip_original: Ipv4Addr::new(192, 168, 0, 0),
             ip_translation: Ipv4Addr::new(10, 0, 0, 0),
             mask: Ipv4Addr::new(255, 255, 0, 0),
+            dnssec: DnxNatDnssec::Strip,
+            resigner: None,
         };
 
         assert_eq!(
@@ -384,12 +1783,24 @@ mod tests {
     fn test_dnx_entry_translate() {
         let dnx_entry = DnxEntry {
             zone: "example.com".to_string(),
-            server: Ipv4Addr::new(192, 168, 0, 1),
-            nat: Some(DnxNatEntry {
+            server: Some(Ipv4Addr::new(192, 168, 0, 1)),
+            zone_file: None,
+            nat: Some(Some(DnxNatEntry {
                 ip_original: Ipv4Addr::new(192, 168, 0, 0),
                 ip_translation: Ipv4Addr::new(10, 0, 0, 0),
                 mask: Ipv4Addr::new(255, 255, 0, 0),
-            }),
+                dnssec: DnxNatDnssec::Strip,
+                resigner: None,
+            })),
+            options: DnxResolverOptions::default(),
+            acl: DnxAcl::default(),
+            exclude: Vec::new(),
+            query_types: None,
+            fallback: None,
+            reject_updates: false,
+            forward_notify: false,
+            transfer_allow: Vec::new(),
+            ecs: None,
         };
 
         assert_eq!(
@@ -401,4 +1812,761 @@ mod tests {
             Ipv4Addr::new(10, 0, 0, 1)
         );
     }
+
+    #[test]
+    fn test_dnx_resolver_options_merged() {
+        let zone = DnxResolverOptions {
+            timeout_ms: Some(500),
+            edns0: Some(false),
+            ..Default::default()
+        };
+        let global = DnxResolverOptions {
+            timeout_ms: Some(10_000),
+            attempts: Some(5),
+            ..Default::default()
+        };
+
+        let merged = zone.merged(&global);
+
+        assert_eq!(merged.timeout_ms, Some(500));
+        assert_eq!(merged.attempts, Some(5));
+        assert_eq!(merged.edns0, Some(false));
+        assert_eq!(merged.cache_size, None);
+    }
+
+    #[test]
+    fn test_dnx_resolver_options_to_resolver_opts() {
+        let options = DnxResolverOptions {
+            timeout_ms: Some(1500),
+            attempts: Some(1),
+            cache_size: Some(16),
+            preserve_intermediates: Some(true),
+            positive_max_ttl: Some(300),
+            negative_min_ttl: Some(5),
+            ..Default::default()
+        }.to_resolver_opts();
+        let default = ResolverOpts::default();
+
+        assert_eq!(options.timeout, Duration::from_millis(1500));
+        assert_eq!(options.attempts, 1);
+        assert_eq!(options.cache_size, 16);
+        assert!(options.preserve_intermediates);
+        assert_eq!(options.positive_max_ttl, Some(Duration::from_secs(300)));
+        assert_eq!(options.negative_min_ttl, Some(Duration::from_secs(5)));
+        assert_eq!(options.positive_min_ttl, default.positive_min_ttl);
+        assert_eq!(options.edns0, default.edns0);
+    }
+
+    #[test]
+    fn test_dnx_config_acl() {
+        let config: DnxConfig = serde_json::from_str(r#"{
+            "zones": [
+                {
+                    "zone": "customer.example.com.",
+                    "server": "192.168.0.1",
+                    "nat": null,
+                    "allow": ["10.1.0.0/16"]
+                }
+            ],
+            "tcp_port": 53,
+            "udp_port": 53,
+            "default_server": "1.1.1.1",
+            "allow": ["10.0.0.0/8"],
+            "deny": ["10.0.99.0/24"]
+        }"#).unwrap();
+
+        assert!(config.acl.permits("10.0.1.1".parse().unwrap()));
+        assert!(!config.acl.permits("10.0.99.1".parse().unwrap()));
+        assert!(!config.acl.permits("172.16.0.1".parse().unwrap()));
+
+        let zone = &config.zones[0];
+        assert!(zone.acl.permits("10.1.2.3".parse().unwrap()));
+        assert!(!zone.acl.permits("10.2.2.3".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_dnx_views_select_routes_by_client() {
+        let config: DnxConfig = serde_json::from_str(r#"{
+            "zones": [
+                { "zone": "corp.example.com.", "server": "192.168.0.1", "nat": null }
+            ],
+            "views": [
+                {
+                    "name": "branch",
+                    "match_clients": ["10.20.0.0/16"],
+                    "zones": [
+                        { "zone": "corp.example.com.", "server": "10.20.0.1", "nat": null }
+                    ],
+                    "default_server": "10.20.0.2"
+                },
+                {
+                    "name": "lab",
+                    "match_clients": ["10.30.0.0/16"],
+                    "zones": []
+                }
+            ],
+            "tcp_port": 53,
+            "udp_port": 53,
+            "default_server": "1.1.1.1"
+        }"#).unwrap();
+        let handler = DnxRequestHandler::from_config(config);
+        let name = &lower("host.corp.example.com.");
+
+        let branch = handler.routes_for("10.20.1.1".parse().unwrap());
+        assert_eq!(branch.view, "branch");
+        assert_eq!(branch.find(name).server, Some(Ipv4Addr::new(10, 20, 0, 1)));
+        assert_eq!(branch.find(&lower("example.org.")).server, Some(Ipv4Addr::new(10, 20, 0, 2)));
+
+        let lab = handler.routes_for("10.30.1.1".parse().unwrap());
+        assert_eq!(lab.view, "lab");
+        assert_eq!(lab.find(name).server, Some(Ipv4Addr::new(1, 1, 1, 1)));
+
+        let other = handler.routes_for("172.16.0.1".parse().unwrap());
+        assert_eq!(other.view, "");
+        assert_eq!(other.find(name).server, Some(Ipv4Addr::new(192, 168, 0, 1)));
+    }
+
+    #[test]
+    fn test_dnx_routes_wildcards_and_exclusions() {
+        let config: DnxConfig = serde_json::from_str(r#"{
+            "zones": [
+                {
+                    "zone": "corp.example.",
+                    "server": "192.168.0.1",
+                    "nat": null,
+                    "exclude": ["public.corp.example."]
+                },
+                {
+                    "zone": "*.svc.example.",
+                    "server": "192.168.0.2",
+                    "nat": null,
+                    "exclude": ["corp.example.", "other.example."]
+                }
+            ],
+            "tcp_port": 53,
+            "udp_port": 53,
+            "default_server": "1.1.1.1"
+        }"#).unwrap();
+        let handler = DnxRequestHandler::from_config(config);
+        let routes = &handler.routes;
+
+        assert_eq!(routes.find(&lower("host.corp.example.")).server, Some(Ipv4Addr::new(192, 168, 0, 1)));
+        assert_eq!(routes.find(&lower("www.public.corp.example.")).server, Some(Ipv4Addr::new(1, 1, 1, 1)));
+        assert_eq!(routes.find(&lower("api.svc.example.")).server, Some(Ipv4Addr::new(192, 168, 0, 2)));
+        assert_eq!(routes.find(&lower("svc.example.")).server, Some(Ipv4Addr::new(1, 1, 1, 1)));
+
+        // excludes outside their own zone are ignored
+        assert!(!is_subzone("corp.example.", "*.svc.example."));
+        assert!(is_subzone("public.corp.example.", "corp.example."));
+        assert!(!is_subzone("corp.example.", "corp.example."));
+    }
+
+    #[test]
+    fn test_dnx_routes_rules_take_precedence() {
+        let config: DnxConfig = serde_json::from_str(r#"{
+            "zones": [
+                { "zone": "local.", "server": "192.168.0.1", "nat": null }
+            ],
+            "rules": [
+                { "glob": "*-dc01.local.", "server": "192.168.0.10", "nat": null },
+                { "regex": "host[0-9]+\\.lab\\.", "server": "192.168.0.20", "nat": null }
+            ],
+            "tcp_port": 53,
+            "udp_port": 53,
+            "default_server": "1.1.1.1"
+        }"#).unwrap();
+        let handler = DnxRequestHandler::from_config(config);
+        let routes = &handler.routes;
+
+        assert_eq!(routes.find(&lower("corp-DC01.local.")).server, Some(Ipv4Addr::new(192, 168, 0, 10)));
+        assert_eq!(routes.find(&lower("corp-dc02.local.")).server, Some(Ipv4Addr::new(192, 168, 0, 1)));
+        assert_eq!(routes.find(&lower("host7.lab.")).server, Some(Ipv4Addr::new(192, 168, 0, 20)));
+        assert_eq!(routes.find(&lower("hostx.lab.")).server, Some(Ipv4Addr::new(1, 1, 1, 1)));
+    }
+
+    #[test]
+    fn test_dnx_routes_by_query_type() {
+        let config: DnxConfig = serde_json::from_str(r#"{
+            "zones": [
+                {
+                    "zone": "_msdcs.corp.example.",
+                    "server": "192.168.0.10",
+                    "nat": null,
+                    "options": { "timeout_ms": 500 },
+                    "query_types": ["SRV", "TXT"],
+                    "fallback": { "server": "192.168.0.20", "nat": null }
+                },
+                {
+                    "zone": "dc.corp.example.",
+                    "server": "192.168.0.10",
+                    "nat": null,
+                    "query_types": ["SRV"]
+                }
+            ],
+            "tcp_port": 53,
+            "udp_port": 53,
+            "default_server": "1.1.1.1"
+        }"#).unwrap();
+        let handler = DnxRequestHandler::from_config(config);
+        let routes = &handler.routes;
+        let find = |name, query_type| routes.for_query_type(routes.find(&lower(name)), query_type);
+
+        assert_eq!(find("_ldap._tcp._msdcs.corp.example.", RecordType::SRV).server, Some(Ipv4Addr::new(192, 168, 0, 10)));
+        assert_eq!(find("_msdcs.corp.example.", RecordType::TXT).server, Some(Ipv4Addr::new(192, 168, 0, 10)));
+
+        let fallback = find("dc01._msdcs.corp.example.", RecordType::A);
+        assert_eq!(fallback.server, Some(Ipv4Addr::new(192, 168, 0, 20)));
+        assert_eq!(fallback.zone, "_msdcs.corp.example.");
+        assert_eq!(fallback.options.timeout_ms, Some(500));
+
+        assert_eq!(find("dc.corp.example.", RecordType::SRV).server, Some(Ipv4Addr::new(192, 168, 0, 10)));
+        assert_eq!(find("dc.corp.example.", RecordType::A).server, Some(Ipv4Addr::new(1, 1, 1, 1)));
+    }
+
+    #[test]
+    fn test_dnx_routes_inherit_from_parent_zones() {
+        let config: DnxConfig = serde_json::from_str(r#"{
+            "zones": [
+                {
+                    "zone": "corp.example.",
+                    "server": "192.168.0.1",
+                    "nat": { "ip_original": "10.0.0.0", "ip_translation": "172.16.0.0", "mask": "255.0.0.0" },
+                    "options": { "timeout_ms": 500, "attempts": 3 },
+                    "allow": ["10.0.0.0/8"]
+                },
+                {
+                    "zone": "branch.corp.example.",
+                    "server": "192.168.0.2",
+                    "options": { "attempts": 1 }
+                },
+                {
+                    "zone": "lab.branch.corp.example.",
+                    "server": "192.168.0.3",
+                    "deny": ["10.0.0.5/32"]
+                },
+                {
+                    "zone": "public.corp.example.",
+                    "server": "192.168.0.4",
+                    "nat": null,
+                    "allow": []
+                }
+            ],
+            "tcp_port": 53,
+            "udp_port": 53,
+            "default_server": "1.1.1.1",
+            "options": { "cache_size": 16 }
+        }"#).unwrap();
+        let handler = DnxRequestHandler::from_config(config);
+        let routes = &handler.routes;
+
+        let branch = routes.find(&lower("host.branch.corp.example."));
+        assert_eq!(branch.server, Some(Ipv4Addr::new(192, 168, 0, 2)));
+        assert_eq!(branch.translate(Ipv4Addr::new(10, 1, 2, 3)), Ipv4Addr::new(172, 1, 2, 3));
+        assert_eq!(branch.options.timeout_ms, Some(500));
+        assert_eq!(branch.options.attempts, Some(1));
+        assert_eq!(branch.options.cache_size, Some(16));
+        assert!(!branch.acl.permits("192.168.1.1".parse().unwrap()));
+
+        // The nearest ancestor wins, and an ACL is inherited only when unset
+        let lab = routes.find(&lower("lab.branch.corp.example."));
+        assert_eq!(lab.options.attempts, Some(1));
+        assert!(lab.acl.permits("192.168.1.1".parse().unwrap()));
+        assert!(!lab.acl.permits("10.0.0.5".parse().unwrap()));
+
+        // NAT set to null and an empty allow list opt out of the parent's
+        let public = routes.find(&lower("www.public.corp.example."));
+        assert_eq!(public.translate(Ipv4Addr::new(10, 1, 2, 3)), Ipv4Addr::new(10, 1, 2, 3));
+        assert!(public.acl.permits("192.168.1.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_normalize_zone() {
+        assert_eq!(normalize_zone("example.com").unwrap(), "example.com.");
+        assert_eq!(normalize_zone("Corp.Example.COM.").unwrap(), "corp.example.com.");
+        assert_eq!(normalize_zone("bücher.example").unwrap(), "xn--bcher-kva.example.");
+        assert_eq!(normalize_zone("*.svc.Example").unwrap(), "*.svc.example.");
+        assert_eq!(normalize_zone("dotted\\.label.example.").unwrap(), "dotted\\.label.example.");
+        assert!(normalize_zone("a..example.").is_err());
+    }
+
+    #[test]
+    fn test_dnx_routes_normalize_zones() {
+        let config: DnxConfig = serde_json::from_str(r#"{
+            "zones": [
+                { "zone": "Bücher.Example", "server": "192.168.0.1", "exclude": ["Public.Bücher.Example"] },
+                { "zone": "*.SVC.example", "server": "192.168.0.2" }
+            ],
+            "tcp_port": 53,
+            "udp_port": 53,
+            "default_server": "1.1.1.1"
+        }"#).unwrap();
+        let handler = DnxRequestHandler::from_config(config);
+        let routes = &handler.routes;
+
+        let entry = routes.find(&lower("www.xn--bcher-kva.example."));
+        assert_eq!(entry.server, Some(Ipv4Addr::new(192, 168, 0, 1)));
+        assert_eq!(entry.zone, "xn--bcher-kva.example.");
+        assert_eq!(routes.find(&lower("www.public.xn--bcher-kva.example.")).server, Some(Ipv4Addr::new(1, 1, 1, 1)));
+        assert_eq!(routes.find(&lower("api.svc.example.")).zone, "*.svc.example.");
+    }
+
+    #[test]
+    fn test_dnx_routes_zone_files() {
+        let path = std::env::temp_dir().join(format!("dnx-routes-zone-{}", std::process::id()));
+        fs::write(&path, "@ IN SOA ns1 hostmaster 1 3600 600 86400 60\n@ IN NS ns1\nns1 IN A 10.0.0.1\n").unwrap();
+
+        let config: DnxConfig = serde_json::from_value(serde_json::json!({
+            "zones": [
+                { "zone": "lab.example.", "zone_file": path },
+                { "zone": "*.svc.example.", "zone_file": path },
+                { "zone": "broken.example." }
+            ],
+            "tcp_port": 53,
+            "udp_port": 53,
+            "default_server": "1.1.1.1"
+        })).unwrap();
+        let handler = DnxRequestHandler::from_config(config);
+        let routes = &handler.routes;
+
+        let entry = routes.find(&lower("www.lab.example."));
+        assert_eq!(entry.zone_file.as_deref(), Some(path.as_path()));
+        assert!(routes.authorities.contains(&lower("lab.example.")));
+        // wildcard zones are loaded at their apex
+        assert!(routes.authorities.contains(&lower("svc.example.")));
+        assert_eq!(routes.find(&lower("www.broken.example.")).server, Some(Ipv4Addr::new(1, 1, 1, 1)));
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_dnx_entry_transfer_allow_and_translate_record() {
+        let entry: DnxEntry = serde_json::from_str(r#"{
+            "zone": "corp.example.",
+            "server": "192.168.0.1",
+            "nat": { "ip_original": "192.168.0.0", "ip_translation": "10.0.0.0", "mask": "255.255.0.0" },
+            "transfer_allow": ["10.1.0.0/16", "::1/128"],
+            "deny": ["10.1.9.0/24"]
+        }"#).unwrap();
+
+        assert!(entry.permits_transfer("10.1.2.3".parse().unwrap()));
+        assert!(entry.permits_transfer("::ffff:10.1.2.3".parse().unwrap()));
+        assert!(entry.permits_transfer("::1".parse().unwrap()));
+        assert!(!entry.permits_transfer("10.2.0.1".parse().unwrap()));
+        assert!(!entry.permits_transfer("10.1.9.1".parse().unwrap()));
+
+        let name = Name::from_ascii("host.corp.example.").unwrap();
+        let record = Record::from_rdata(name.clone(), 300, RData::A(Ipv4Addr::new(192, 168, 1, 1).into()));
+        let translated = entry.translate_record(&record);
+        assert_eq!(translated.data(), Some(&RData::A(Ipv4Addr::new(10, 0, 1, 1).into())));
+        assert_eq!(translated.name(), &name);
+        assert_eq!(translated.ttl(), 300);
+
+        let record = Record::from_rdata(name, 300, RData::CNAME(rdata::CNAME(Name::from_ascii("other.corp.example.").unwrap())));
+        assert_eq!(entry.translate_record(&record), record);
+    }
+
+    #[test]
+    fn test_dnx_entry_translate_records_handles_signatures() {
+        let entry: DnxEntry = serde_json::from_str(r#"{
+            "zone": "*.Corp.Example",
+            "server": "192.168.0.1",
+            "nat": {
+                "ip_original": "192.168.0.0",
+                "ip_translation": "10.0.0.0",
+                "mask": "255.255.0.0",
+                "dnssec": { "resign": { "key_file": "/nonexistent/corp.pk8", "algorithm": "ECDSAP256SHA256" } }
+            }
+        }"#).unwrap();
+
+        // the signer defaults to the zone, and signatures are stripped when
+        // its key can't be loaded
+        let entry = entry.normalized().unwrap().prepared(&DnxResolverOptions::default());
+        let nat = entry.nat().unwrap();
+        let DnxNatDnssec::Resign(ref key) = nat.dnssec else {
+            panic!("expected a signing key");
+        };
+        assert_eq!(key.signer_name.as_deref(), Some("corp.example."));
+        assert!(nat.resigner.is_none());
+
+        let name = Name::from_ascii("host.corp.example.").unwrap();
+        let rrsig = |record_type| {
+            let rrsig = RRSIG::new(
+                record_type, Algorithm::ECDSAP256SHA256, 3, 300, 2_000_000_000, 1_000_000_000, 1, name.clone(), vec![0; 64],
+            );
+            Record::from_rdata(name.clone(), 300, RData::DNSSEC(DNSSECRData::RRSIG(rrsig)))
+        };
+        let records = [
+            Record::from_rdata(name.clone(), 300, RData::A(Ipv4Addr::new(192, 168, 1, 1).into())),
+            rrsig(RecordType::A),
+            Record::from_rdata(name.clone(), 300, RData::TXT(rdata::TXT::new(vec!["host".to_string()]))),
+            rrsig(RecordType::TXT),
+        ];
+
+        let (translated, stripped) = entry.translate_records(&records);
+        assert!(stripped);
+        assert_eq!(translated.len(), 3);
+        assert_eq!(translated[0].data(), Some(&RData::A(Ipv4Addr::new(10, 0, 1, 1).into())));
+        assert_eq!(translated[1..], records[2..]);
+
+        // answers NAT leaves alone keep their signatures
+        let (translated, stripped) = entry.translate_records(&records[2..]);
+        assert!(!stripped);
+        assert_eq!(translated, records[2..]);
+
+        // queries ask the upstream for signatures, and their answers go
+        // through the same translation
+        let query = Query::query(name.clone(), RecordType::A);
+        let message = subnet_query(&query, None, true);
+        assert!(message.extensions().as_ref().is_some_and(Edns::dnssec_ok));
+        assert!(!subnet_query(&query, None, false).extensions().as_ref().is_some_and(Edns::dnssec_ok));
+
+        let mut response = Message::new();
+        response.set_message_type(MessageType::Response);
+        response.add_answers(records.clone());
+        let answer = translated_answer(&entry, &response);
+        assert_eq!(answer.answers[0].data(), Some(&RData::A(Ipv4Addr::new(10, 0, 1, 1).into())));
+        assert_eq!(answer.answers[1..], records[2..]);
+        assert!(!answer.authentic_data);
+    }
+
+    #[tokio::test]
+    async fn test_dnx_relays_transfers_message_by_message() {
+        let handler = handler(serde_json::json!({
+            "zones": [
+                {
+                    "zone": "corp.example.",
+                    "server": "192.168.0.1",
+                    "nat": { "ip_original": "192.168.0.0", "ip_translation": "10.0.0.0", "mask": "255.255.0.0" },
+                    "transfer_allow": ["127.0.0.0/8"]
+                }
+            ]
+        }));
+        let zone = Name::from_ascii("corp.example.").unwrap();
+        let soa = Record::from_rdata(zone.clone(), 300, RData::SOA(rdata::SOA::new(zone.clone(), zone.clone(), 5, 3600, 600, 86400, 60)));
+        let a = |host: &str, ip: [u8; 4]| {
+            Record::from_rdata(Name::from_ascii(host).unwrap(), 300, RData::A(Ipv4Addr::from(ip).into()))
+        };
+
+        // an upstream streaming the AXFR over two messages
+        let tcp = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server = tcp.local_addr().unwrap();
+        let messages = [
+            vec![soa.clone(), a("a.corp.example.", [192, 168, 1, 1])],
+            vec![a("b.corp.example.", [192, 168, 1, 2]), soa.clone()],
+        ];
+        tokio::spawn(async move {
+            let (mut stream, _) = tcp.accept().await.unwrap();
+            let request = upstream::read_tcp(&mut stream).await.unwrap();
+            for records in messages {
+                let mut response = request.clone();
+                response.set_message_type(MessageType::Response);
+                response.add_answers(records);
+                upstream::write_tcp(&mut stream, &response.to_vec().unwrap()).await.unwrap();
+            }
+        });
+
+        let mut message = Message::new();
+        message.set_id(21);
+        message.add_query(Query::query(zone, RecordType::AXFR));
+        let request = MessageRequest::from_bytes(&message.to_vec().unwrap()).unwrap();
+        let request = Request::new(request, "127.0.0.1:5353".parse().unwrap(), ServerProtocol::Tcp);
+
+        let transfer = DnxZoneTransfer::start(server, &upstream::to_message(&request), Duration::from_secs(5)).await.unwrap();
+        let mut responses = Responses::default();
+        let entry = handler.routes.find(&lower("corp.example.")).clone();
+        handler.relay_transfer_messages(&request, &mut responses, &entry, transfer).await.unwrap();
+
+        let responses = responses.0.lock().unwrap();
+        assert_eq!(responses.len(), 2);
+        assert!(responses.iter().all(|response| response.id() == 21));
+        assert_eq!(responses[0].answers(), &[soa.clone(), a("a.corp.example.", [10, 0, 1, 1])]);
+        assert_eq!(responses[1].answers(), &[a("b.corp.example.", [10, 0, 1, 2]), soa]);
+    }
+
+    #[test]
+    fn test_dnx_dnssec_validates_default_server_only() {
+        let anchors = std::env::temp_dir().join(format!("dnx-server-anchors-{}", std::process::id()));
+        fs::write(&anchors, "lab.example. 300 IN DNSKEY 257 3 13 oJMRESz5E4gYzS/q6XDrvU1qMPYIjCWzJaOau8XNEZeqCvKu5JLsz8WiWf4s7WPw\n").unwrap();
+        let config = |trust_anchors: &Path| -> DnxConfig {
+            serde_json::from_value(serde_json::json!({
+                "zones": [
+                    { "zone": "corp.example.com.", "server": "192.168.0.1", "nat": null }
+                ],
+                "views": [
+                    {
+                        "name": "branch",
+                        "match_clients": ["10.20.0.0/16"],
+                        "zones": [
+                            { "zone": "corp.example.com.", "server": "10.20.0.1", "nat": null }
+                        ]
+                    }
+                ],
+                "tcp_port": 53,
+                "udp_port": 53,
+                "default_server": "1.1.1.1",
+                "dnssec": { "trust_anchors": trust_anchors }
+            })).unwrap()
+        };
+
+        let handler = DnxRequestHandler::from_config(config(&anchors));
+        for routes in [&handler.routes, handler.routes_for("10.20.1.1".parse().unwrap())] {
+            assert!(routes.validator_for(routes.find(&lower("example.org."))).is_some());
+            assert!(routes.validator_for(routes.find(&lower("host.corp.example.com."))).is_none());
+        }
+
+        // validation is off when the trust anchors can't be loaded
+        let handler = DnxRequestHandler::from_config(config(&anchors.with_extension("missing")));
+        assert!(handler.routes.validator.is_none());
+
+        fs::remove_file(anchors).unwrap();
+    }
+
+    #[test]
+    fn test_dnx_serves_stale_answers_within_max_stale_ttl() {
+        let handler = handler(serde_json::json!({
+            "zones": [
+                {
+                    "zone": "corp.example.",
+                    "server": "192.168.0.1",
+                    "nat": null,
+                    "options": { "max_stale_ttl": 3600 }
+                }
+            ]
+        }));
+        let answer = |name: &str| DnxCachedAnswer {
+            response_code: ResponseCode::NoError,
+            answers: vec![Record::from_rdata(Name::from_ascii(name).unwrap(), 60, RData::A(Ipv4Addr::new(10, 0, 0, 1).into()))],
+            name_servers: Vec::new(),
+            authentic_data: false,
+            validated: false,
+        };
+        let cached = Instant::now() - Duration::from_secs(120);
+
+        let entry = handler.routes.find(&lower("host.corp.example."));
+        cache_answer(&handler.cache, key("host.corp.example."), None, &answer("host.corp.example."), &entry.options, cached);
+        let stale = handler.stale_answer(&key("host.corp.example."), None, false).unwrap();
+        assert_eq!(stale.answers[0].ttl(), crate::cache::STALE_TTL);
+        assert_eq!(handler.metrics.stale_answers_served.load(std::sync::atomic::Ordering::Relaxed), 1);
+
+        // validating clients only get answers that were validated
+        assert_eq!(handler.stale_answer(&key("host.corp.example."), None, true), None);
+        let validated = DnxCachedAnswer { validated: true, ..answer("host.corp.example.") };
+        cache_answer(&handler.cache, key("host.corp.example."), None, &validated, &entry.options, cached);
+        assert!(handler.stale_answer(&key("host.corp.example."), None, true).is_some());
+        assert_eq!(handler.metrics.stale_answers_served.load(std::sync::atomic::Ordering::Relaxed), 2);
+
+        // zones without max_stale_ttl drop answers once they expire
+        let entry = handler.routes.find(&lower("example.org."));
+        cache_answer(&handler.cache, key("example.org."), None, &answer("example.org."), &entry.options, cached);
+        assert_eq!(handler.stale_answer(&key("example.org."), None, false), None);
+        assert_eq!(handler.metrics.stale_answers_served.load(std::sync::atomic::Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn test_dnx_serves_stale_negative_answers() {
+        // an upstream that never answers
+        let udp = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let handler = handler(serde_json::json!({
+            "zones": [],
+            "default_server": [{ "address": "127.0.0.1", "port": udp.local_addr().unwrap().port() }],
+            "options": { "timeout_ms": 100, "attempts": 1, "max_stale_ttl": 3600 }
+        }));
+
+        let zone = Name::from_ascii("example.org.").unwrap();
+        let soa = rdata::SOA::new(zone.clone(), zone.clone(), 1, 3600, 600, 86400, 60);
+        let answer = DnxCachedAnswer {
+            response_code: ResponseCode::NXDomain,
+            answers: Vec::new(),
+            name_servers: vec![Record::from_rdata(zone, 60, RData::SOA(soa))],
+            authentic_data: false,
+            validated: false,
+        };
+        let entry = handler.routes.find(&lower("missing.example.org."));
+        cache_answer(&handler.cache, key("missing.example.org."), None, &answer, &entry.options, Instant::now() - Duration::from_secs(120));
+
+        let mut message = Message::new();
+        message.set_id(5).set_recursion_desired(true);
+        message.add_query(Query::query(Name::from_ascii("missing.example.org.").unwrap(), RecordType::A));
+        let request = MessageRequest::from_bytes(&message.to_vec().unwrap()).unwrap();
+        let request = Request::new(request, "127.0.0.1:5353".parse().unwrap(), ServerProtocol::Udp);
+        let mut responses = Responses::default();
+        handler.do_handle_request(&request, &mut responses).await.unwrap();
+
+        let responses = responses.0.lock().unwrap();
+        assert_eq!(responses[0].response_code(), ResponseCode::NXDomain);
+        assert!(responses[0].answers().is_empty());
+        assert_eq!(responses[0].name_servers()[0].record_type(), RecordType::SOA);
+        assert_eq!(responses[0].name_servers()[0].ttl(), crate::cache::STALE_TTL);
+    }
+
+    #[test]
+    fn test_dnx_caches_negative_answers_for_soa_minimum() {
+        let handler = handler(serde_json::json!({
+            "zones": [
+                {
+                    "zone": "corp.example.",
+                    "server": "192.168.0.1",
+                    "nat": null,
+                    "options": { "negative_max_ttl": 60 }
+                }
+            ]
+        }));
+        let now = Instant::now();
+        let error = |name: &str, ttl: u32, minimum: u32| -> ResolveError {
+            let zone = Name::from_ascii(name).unwrap().base_name();
+            let soa = rdata::SOA::new(zone.clone(), zone.clone(), 1, 3600, 600, 86400, minimum);
+            ResolveErrorKind::NoRecordsFound {
+                query: Box::new(Query::query(Name::from_ascii(name).unwrap(), RecordType::A)),
+                soa: Some(Box::new(Record::from_rdata(zone, ttl, soa))),
+                negative_ttl: None,
+                response_code: ResponseCode::NXDomain,
+                trusted: true,
+            }.into()
+        };
+        let cached_ttl = |name: &str| handler.cache.get(&key(name), None, now).map(|hit| hit.answer.ttl());
+
+        // the SOA minimum bounds the SOA record's TTL
+        let answer = negative_answer(&error("missing.example.org.", 3600, 300)).unwrap();
+        assert_eq!(answer.response_code, ResponseCode::NXDomain);
+        assert_eq!(answer.name_servers[0].ttl(), 300);
+        let entry = handler.routes.find(&lower("missing.example.org."));
+        cache_answer(&handler.cache, key("missing.example.org."), None, &answer, &entry.options, now);
+        assert_eq!(cached_ttl("missing.example.org."), Some(Some(300)));
+
+        // capped by the zone's negative_max_ttl, or MAX_NEGATIVE_TTL without one
+        let answer = negative_answer(&error("missing.corp.example.", 3600, 300)).unwrap();
+        let entry = handler.routes.find(&lower("missing.corp.example."));
+        cache_answer(&handler.cache, key("missing.corp.example."), None, &answer, &entry.options, now);
+        assert_eq!(cached_ttl("missing.corp.example."), Some(Some(60)));
+
+        let answer = negative_answer(&error("long.example.org.", 86400, 86400)).unwrap();
+        let entry = handler.routes.find(&lower("long.example.org."));
+        cache_answer(&handler.cache, key("long.example.org."), None, &answer, &entry.options, now);
+        assert_eq!(cached_ttl("long.example.org."), Some(Some(MAX_NEGATIVE_TTL)));
+
+        // errors without an SOA aren't cached
+        let error: ResolveError = ResolveErrorKind::Timeout.into();
+        assert_eq!(negative_answer(&error), None);
+    }
+
+    #[tokio::test]
+    async fn test_dnx_prefetch_refreshes_past_the_resolver_cache() {
+        // an upstream answering with a new address each time
+        let udp = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server = udp.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buffer = vec![0; 4096];
+            for last in 1.. {
+                let (len, src) = udp.recv_from(&mut buffer).await.unwrap();
+                let mut response = Message::from_vec(&buffer[..len]).unwrap();
+                let name = response.queries()[0].name().clone();
+                response.set_message_type(MessageType::Response);
+                response.add_answer(Record::from_rdata(name, 100, RData::A(Ipv4Addr::new(10, 0, 0, last).into())));
+                udp.send_to(&response.to_vec().unwrap(), src).await.unwrap();
+            }
+        });
+
+        let handler = handler(serde_json::json!({
+            "zones": [],
+            "default_server": [{ "address": "127.0.0.1", "port": server.port() }],
+            "prefetch": { "concurrency": 1, "min_hits": 1 }
+        }));
+        let routes = &handler.routes;
+        let entry = routes.find(&lower("cdn.example."));
+        let query = Query::query(Name::from_ascii("cdn.example.").unwrap(), RecordType::A);
+
+        // the answer was cached 95 seconds ago, and the resolver still has it
+        let resolver = handler.resolver(routes, entry).unwrap();
+        let answer = fetch_resolved(resolver, entry, &query).await.unwrap();
+        cache_answer(&handler.cache, key("cdn.example."), None, &answer, &entry.options, Instant::now() - Duration::from_secs(95));
+        assert_eq!(fetch_resolved(resolver, entry, &query).await.unwrap().answers, answer.answers);
+
+        handler.prefetch(key("cdn.example."), None, routes, entry, None);
+        let later = Instant::now() + Duration::from_secs(30);
+        let refreshed = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                if let Some(hit) = handler.cache.get(&key("cdn.example."), None, later) {
+                    return hit.answer;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }).await.unwrap();
+        assert_eq!(refreshed.answers[0].data(), Some(&RData::A(Ipv4Addr::new(10, 0, 0, 2).into())));
+    }
+
+    #[test]
+    fn test_dnx_resolver_pool_shares_resolvers_by_upstream() {
+        let handler = handler(serde_json::json!({
+            "zones": [
+                { "zone": "corp.example.", "server": "192.168.0.1", "nat": null },
+                { "zone": "lab.example.", "server": "192.168.0.1", "nat": null },
+                {
+                    "zone": "slow.example.",
+                    "server": "192.168.0.1",
+                    "nat": null,
+                    "options": { "timeout_ms": 10000 }
+                },
+                {
+                    "zone": "stale.example.",
+                    "server": "192.168.0.1",
+                    "nat": null,
+                    "options": { "max_stale_ttl": 3600 }
+                },
+                { "zone": "local.example.", "zone_file": "local.example.zone", "nat": null }
+            ],
+            "views": [
+                {
+                    "name": "branch",
+                    "match_clients": ["10.20.0.0/16"],
+                    "zones": [
+                        { "zone": "corp.example.", "server": "192.168.0.1", "nat": null }
+                    ]
+                }
+            ]
+        }));
+
+        // 192.168.0.1 with and without a timeout, and 1.1.1.1
+        assert_eq!(handler.resolvers.resolvers.len(), 3);
+
+        let resolver = |name: &str| handler.resolver(&handler.routes, handler.routes.find(&lower(name))).unwrap() as *const _;
+        assert_eq!(resolver("host.corp.example."), resolver("host.lab.example."));
+        assert_ne!(resolver("host.corp.example."), resolver("host.slow.example."));
+        // serving stale answers is up to the handler's cache
+        assert_eq!(resolver("host.corp.example."), resolver("host.stale.example."));
+        let branch = handler.routes_for("10.20.1.1".parse().unwrap());
+        assert_eq!(handler.resolver(branch, branch.find(&lower("host.corp.example."))).unwrap() as *const _, resolver("host.corp.example."));
+
+        assert!(handler.resolver(&handler.routes, handler.routes.find(&lower("host.local.example."))).is_err());
+    }
+
+    #[test]
+    fn test_dnx_default_server_groups_and_system() {
+        let handler = handler(serde_json::json!({
+            "zones": [
+                { "zone": "corp.example.", "server": "192.168.0.1", "nat": null }
+            ],
+            "views": [
+                {
+                    "name": "host",
+                    "match_clients": ["10.20.0.0/16"],
+                    "zones": [],
+                    "default_server": "system"
+                }
+            ],
+            "default_server": [
+                { "address": "9.9.9.9", "protocol": "tls", "tls_name": "dns.quad9.net" },
+                { "address": "149.112.112.112", "protocol": "https", "tls_name": "dns.quad9.net" }
+            ]
+        }));
+
+        let default = handler.routes.find(&lower("example.org."));
+        assert_eq!(default.server, None);
+        assert!(matches!(handler.routes.upstream(default).unwrap().servers, DnxNameServers::Group(ref servers) if servers.len() == 2));
+        assert!(handler.resolver(&handler.routes, default).is_ok());
+        assert!(handler.resolver(&handler.routes, handler.routes.find(&lower("host.corp.example."))).is_ok());
+
+        let host = handler.routes_for("10.20.1.1".parse().unwrap());
+        let upstream = host.upstream(host.find(&lower("example.org."))).unwrap();
+        assert_eq!(upstream.servers, DnxNameServers::System(crate::upstream::DnxSystemNameServers::System));
+    }
 }
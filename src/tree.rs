@@ -1,4 +1,4 @@
-use std::{marker::PhantomData, collections::HashMap, hash::Hash};
+use std::{borrow::Borrow, marker::PhantomData, collections::HashMap, hash::Hash};
 
 use hickory_server::proto::rr::{LowerName, Name};
 use serde::{Serialize, Deserialize};
@@ -7,42 +7,219 @@ pub trait TreeSortable<T: PartialEq> {
     fn get_path(&self) -> Vec<T>;
 }
 
+/// A path segment of a `Tree`. A wildcard label as the first segment of a
+/// path matches every path strictly below the rest of it.
+pub trait TreeLabel: Eq + Hash {
+    fn is_wildcard(&self) -> bool;
+
+    fn wildcard() -> Self;
+}
+
+/// A DNS label, stored lowercase so trees keyed on labels match names
+/// case-insensitively. Borrows as `[u8]`, so lookups can use the labels of a
+/// `LowerName` directly.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Label(Box<[u8]>);
+
+impl Label {
+    pub fn new(label: &[u8]) -> Self {
+        Label(label.to_ascii_lowercase().into_boxed_slice())
+    }
+}
+
+impl Borrow<[u8]> for Label {
+    fn borrow(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl TreeLabel for Label {
+    fn is_wildcard(&self) -> bool {
+        *self.0 == *b"*"
+    }
+
+    fn wildcard() -> Self {
+        Label::new(b"*")
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Tree<V, T>
 where
-    V: Eq + Hash,
+    V: TreeLabel,
     T: TreeSortable<V>
 {
     root: TreeNode<V, T>,
+    len: usize,
+}
+
+impl<V, T> Default for Tree<V, T>
+where
+    V: TreeLabel,
+    T: TreeSortable<V>
+{
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl<V, T> Tree<V, T>
 where
-    V: Eq + Hash,
+    V: TreeLabel,
     T: TreeSortable<V>
 {
     pub fn new() -> Self {
         Tree {
-            root: TreeNode {
-                value: None,
-                children: HashMap::new(),
-                _phantom: PhantomData,
-            },
+            root: TreeNode::new(),
+            len: 0,
         }
     }
 
+    /// Number of values in the tree, counting wildcards separately from the
+    /// value at their apex.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
     pub fn insert(&mut self, value: T) -> Option<T> {
-        self.root.add_child(value, None)
+        let last = self.root.add_child(value, None);
+        if last.is_none() {
+            self.len += 1;
+        }
+        last
     }
 
+    /// Removes the value at exactly `path`, or the wildcard below the rest of
+    /// `path` if it starts with a wildcard label. Branches left without
+    /// values or exclusions are pruned.
+    pub fn remove<U>(&mut self, path: U) -> Option<T>
+    where
+        U: TreeSortable<V>
+    {
+        let removed = self.root.remove(path.get_path());
+        if removed.is_some() {
+            self.len -= 1;
+        }
+        removed
+    }
+
+    /// Keeps only the values for which `keep` returns true, pruning branches
+    /// left empty.
+    pub fn retain<F>(&mut self, mut keep: F)
+    where
+        F: FnMut(&T) -> bool
+    {
+        self.len -= self.root.retain(&mut keep);
+    }
+
+    /// Iterates over every value along with its full path, in the same order
+    /// as `TreeSortable::get_path`. The iteration order is unspecified.
+    pub fn iter(&self) -> Iter<'_, V, T>
+    where
+        V: Clone
+    {
+        Iter {
+            stack: vec![(Vec::new(), &self.root)],
+            pending: Vec::new(),
+        }
+    }
+
+    /// Compares this tree against `other`, a newer version of it. Only values
+    /// are compared; exclusions are ignored.
+    pub fn diff<'a>(&'a self, other: &'a Tree<V, T>) -> TreeDiff<'a, V, T>
+    where
+        V: Clone,
+        T: PartialEq
+    {
+        let mut diff = TreeDiff {
+            added: Vec::new(),
+            removed: Vec::new(),
+            changed: Vec::new(),
+        };
+
+        for (path, value) in self.iter() {
+            match other.get(path.clone()) {
+                None => diff.removed.push((path, value)),
+                Some(other) if other != value => diff.changed.push((path, value, other)),
+                Some(_) => (),
+            }
+        }
+
+        for (path, value) in other.iter() {
+            if self.get(path.clone()).is_none() {
+                diff.added.push((path, value));
+            }
+        }
+
+        diff
+    }
+
+    /// Marks `path` and everything below it as matching nothing, so `find`
+    /// doesn't fall back to an enclosing value. Values inserted at or below
+    /// the excluded path still match.
+    pub fn exclude<U>(&mut self, path: U)
+    where
+        U: TreeSortable<V>
+    {
+        let path = path.get_path();
+        self.root.exclude(path);
+    }
+
+    /// Finds the value for the most specific match of `path`. An exact value
+    /// takes precedence over a wildcard at the same level, a wildcard over
+    /// the value of the path it's under, and an exclusion stops the search
+    /// from falling back to anything above it.
     pub fn find<U>(&self, path: U) -> Option<&T>
     where
         U: TreeSortable<V>
     {
         let path = path.get_path();
+        self.find_iter(path.iter().rev())
+    }
+
+    /// Finds every value matching `path`, from the root to the most specific
+    /// match, each with the number of labels of `path` it matched. A wildcard
+    /// follows the value of the path it's under, and an exclusion drops every
+    /// match above it, so the last match is the one `find` returns.
+    pub fn find_all<U>(&self, path: U) -> Vec<(usize, &T)>
+    where
+        U: TreeSortable<V>
+    {
+        let path = path.get_path();
+        self.root.find_all(path.iter().rev())
+    }
+
+    /// Like `find_all`, but without the value at exactly `path`, so only the
+    /// values `path` is nested in are returned.
+    pub fn ancestors<U>(&self, path: U) -> Vec<(usize, &T)>
+    where
+        U: TreeSortable<V>
+    {
+        let path = path.get_path();
+        let depth = path.len();
+
+        let mut matches = self.root.find_all(path.iter().rev());
+        matches.retain(|(matched, _)| *matched < depth);
+        matches
+    }
+
+    /// Like `find`, but walks `path` from the root end without collecting it
+    /// first.
+    pub fn find_iter<'a, Q, I>(&self, path: I) -> Option<&T>
+    where
+        V: Borrow<Q>,
+        Q: Eq + Hash + ?Sized + 'a,
+        I: IntoIterator<Item = &'a Q>,
+    {
         self.root.find(path)
     }
 
+    /// Gets the value at exactly `path`, or the wildcard below the rest of
+    /// `path` if it starts with a wildcard label.
     pub fn get<U>(&self, path: U) -> Option<&T>
     where
         U: TreeSortable<V>
@@ -52,22 +229,107 @@ where
     }
 }
 
+/// The differences between two trees, as returned by `Tree::diff`.
+#[derive(Debug)]
+pub struct TreeDiff<'a, V, T> {
+    /// Values only in the newer tree.
+    pub added: Vec<(Vec<V>, &'a T)>,
+    /// Values only in the older tree.
+    pub removed: Vec<(Vec<V>, &'a T)>,
+    /// Values at the same path that differ, old then new.
+    pub changed: Vec<(Vec<V>, &'a T, &'a T)>,
+}
+
+impl<V, T> TreeDiff<'_, V, T> {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Iterator over the values of a `Tree` and their paths.
+pub struct Iter<'a, V, T>
+where
+    V: TreeLabel,
+    T: TreeSortable<V>
+{
+    // Paths are kept root first while walking, and reversed when yielded
+    stack: Vec<(Vec<V>, &'a TreeNode<V, T>)>,
+    pending: Vec<(Vec<V>, &'a T)>,
+}
+
+impl<'a, V, T> Iterator for Iter<'a, V, T>
+where
+    V: TreeLabel + Clone,
+    T: TreeSortable<V>
+{
+    type Item = (Vec<V>, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(item) = self.pending.pop() {
+                return Some(item);
+            }
+
+            let (path, node) = self.stack.pop()?;
+
+            if let Some(ref value) = node.wildcard {
+                let mut path = path.clone();
+                path.push(V::wildcard());
+                path.reverse();
+                self.pending.push((path, value));
+            }
+            if let Some(ref value) = node.value {
+                self.pending.push((path.iter().rev().cloned().collect(), value));
+            }
+
+            for (label, child) in &node.children {
+                let mut path = path.clone();
+                path.push(label.clone());
+                self.stack.push((path, child));
+            }
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct TreeNode<V, T> 
 where
-    V: Eq + Hash,
+    V: TreeLabel,
     T: TreeSortable<V>
 {
     value: Option<T>,
+    wildcard: Option<T>,
+    excluded: bool,
     children: HashMap<V, TreeNode<V, T>>,
     _phantom: PhantomData<V>,
 }
 
+impl<T> Tree<Label, T>
+where
+    T: TreeSortable<Label>
+{
+    /// Finds the value for `name` without allocating.
+    pub fn find_name(&self, name: &LowerName) -> Option<&T> {
+        let name: &Name = name.borrow();
+        self.find_iter(name.iter().rev())
+    }
+}
+
 impl<V, T> TreeNode<V, T>
 where
-    V: Eq + Hash,
+    V: TreeLabel,
     T: TreeSortable<V>
 {
+    fn new() -> Self {
+        TreeNode {
+            value: None,
+            wildcard: None,
+            excluded: false,
+            children: HashMap::new(),
+            _phantom: PhantomData,
+        }
+    }
+
     fn add_child(&mut self, value: T, path: Option<Vec<V>>) -> Option<T> {
         let mut path = path.unwrap_or_else(|| value.get_path());
 
@@ -77,28 +339,137 @@ where
             return last;
         }
 
+        if path.len() == 1 && path[0].is_wildcard() {
+            let last = self.wildcard.take();
+            self.wildcard = Some(value);
+            return last;
+        }
+
         let next = path.pop().unwrap();
-        self.children.entry(next).or_insert_with(|| {
-            TreeNode {
-                value: None,
-                children: HashMap::new(),
-                _phantom: PhantomData,
-            }
-        }).add_child(value, Some(path))
+        self.children.entry(next).or_insert_with(TreeNode::new).add_child(value, Some(path))
     }
 
-    fn find(&self, mut path: Vec<V>) -> Option<&T> {
+    fn remove(&mut self, mut path: Vec<V>) -> Option<T> {
         if path.is_empty() {
-            return self.value.as_ref();
+            return self.value.take();
+        }
+
+        if path.len() == 1 && path[0].is_wildcard() {
+            return self.wildcard.take();
         }
 
         let next = path.pop().unwrap();
-        let next = self.children.get(&next);
+        let child = self.children.get_mut(&next)?;
+        let removed = child.remove(path);
+        if child.is_empty() {
+            self.children.remove(&next);
+        }
+        removed
+    }
+
+    /// Drops the values `keep` rejects, returning how many were dropped.
+    fn retain<F>(&mut self, keep: &mut F) -> usize
+    where
+        F: FnMut(&T) -> bool
+    {
+        let mut removed = 0;
+
+        if self.value.as_ref().is_some_and(|value| !keep(value)) {
+            self.value = None;
+            removed += 1;
+        }
+        if self.wildcard.as_ref().is_some_and(|value| !keep(value)) {
+            self.wildcard = None;
+            removed += 1;
+        }
+
+        self.children.retain(|_, child| {
+            removed += child.retain(keep);
+            !child.is_empty()
+        });
+
+        removed
+    }
+
+    /// True if this node holds nothing and can be pruned.
+    fn is_empty(&self) -> bool {
+        self.value.is_none() && self.wildcard.is_none() && !self.excluded && self.children.is_empty()
+    }
+
+    fn exclude(&mut self, mut path: Vec<V>) {
+        match path.pop() {
+            None => self.excluded = true,
+            Some(next) => self.children.entry(next).or_insert_with(TreeNode::new).exclude(path),
+        }
+    }
+
+    /// The value matching this node's own path.
+    fn apex_value<'a>(&'a self, inherited: Option<&'a T>) -> Option<&'a T> {
+        match self.value {
+            Some(ref value) => Some(value),
+            None if self.excluded => None,
+            None => inherited,
+        }
+    }
+
+    /// The value matching paths strictly below this node.
+    fn descendant_value<'a>(&'a self, inherited: Option<&'a T>) -> Option<&'a T> {
+        self.wildcard.as_ref().or_else(|| self.apex_value(inherited))
+    }
+
+    fn find<'a, Q, I>(&self, path: I) -> Option<&T>
+    where
+        V: Borrow<Q>,
+        Q: Eq + Hash + ?Sized + 'a,
+        I: IntoIterator<Item = &'a Q>,
+    {
+        let mut node = self;
+        let mut inherited = None;
+
+        for next in path {
+            inherited = node.descendant_value(inherited);
+            match node.children.get(next) {
+                None => return inherited,
+                Some(next) => node = next,
+            }
+        }
+
+        node.apex_value(inherited)
+    }
+
+    fn find_all<'a, Q, I>(&self, path: I) -> Vec<(usize, &T)>
+    where
+        V: Borrow<Q>,
+        Q: Eq + Hash + ?Sized + 'a,
+        I: IntoIterator<Item = &'a Q>,
+    {
+        let mut node = self;
+        let mut depth = 0;
+        let mut matches = Vec::new();
+        let mut path = path.into_iter();
+
+        loop {
+            if node.excluded {
+                matches.clear();
+            }
+            if let Some(ref value) = node.value {
+                matches.push((depth, value));
+            }
 
-        match next {
-            None => self.value.as_ref(),
-            Some(next) => next.find(path),
+            let Some(next) = path.next() else {
+                break;
+            };
+            if let Some(ref wildcard) = node.wildcard {
+                matches.push((depth + 1, wildcard));
+            }
+            match node.children.get(next) {
+                None => break,
+                Some(next) => node = next,
+            }
+            depth += 1;
         }
+
+        matches
     }
 
     fn get(&self, mut path: Vec<V>) -> Option<&T> {
@@ -106,20 +477,39 @@ where
             return self.value.as_ref();
         }
 
+        if path.len() == 1 && path[0].is_wildcard() {
+            return self.wildcard.as_ref();
+        }
+
         let next = path.pop().unwrap();
         self.children.get(&next)?.get(path)
     }
 }
 
+impl TreeLabel for String {
+    fn is_wildcard(&self) -> bool {
+        self == "*"
+    }
+
+    fn wildcard() -> Self {
+        "*".to_owned()
+    }
+}
+
 impl TreeSortable<String> for &str {
+    /// Splits the string on dots. Empty labels are skipped, so the trailing
+    /// dot is optional.
     fn get_path(&self) -> Vec<String> {
-        self.split(".").map(|s| s.to_owned()).collect()
+        self.split('.')
+            .filter(|label| !label.is_empty())
+            .map(|label| label.to_owned())
+            .collect()
     }
 }
 
 impl TreeSortable<String> for String {
     fn get_path(&self) -> Vec<String> {
-        self.split(".").map(|s| s.to_owned()).collect()
+        self.as_str().get_path()
     }
 }
 
@@ -133,4 +523,316 @@ impl TreeSortable<String> for &Name {
     fn get_path(&self) -> Vec<String> {
         self.to_string().get_path()
     }
-}
\ No newline at end of file
+}
+
+impl<V> TreeSortable<V> for Vec<V>
+where
+    V: TreeLabel + Clone
+{
+    fn get_path(&self) -> Vec<V> {
+        self.clone()
+    }
+}
+
+impl TreeSortable<Label> for &str {
+    /// Parses the string as a DNS name, so escaped dots stay within a label.
+    /// Strings that aren't valid names are split on dots instead.
+    fn get_path(&self) -> Vec<Label> {
+        match Name::from_ascii(self) {
+            Ok(name) => (&name).get_path(),
+            Err(_) => self.split('.')
+                .filter(|label| !label.is_empty())
+                .map(|label| Label::new(label.as_bytes()))
+                .collect(),
+        }
+    }
+}
+
+impl TreeSortable<Label> for String {
+    fn get_path(&self) -> Vec<Label> {
+        self.as_str().get_path()
+    }
+}
+
+impl TreeSortable<Label> for &LowerName {
+    fn get_path(&self) -> Vec<Label> {
+        let name: &Name = (*self).borrow();
+        name.get_path()
+    }
+}
+
+impl TreeSortable<Label> for &Name {
+    fn get_path(&self) -> Vec<Label> {
+        self.iter().map(Label::new).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Zone(&'static str);
+
+    impl TreeSortable<String> for Zone {
+        fn get_path(&self) -> Vec<String> {
+            self.0.get_path()
+        }
+    }
+
+    fn tree(zones: &[&'static str]) -> Tree<String, Zone> {
+        let mut tree = Tree::new();
+        for zone in zones {
+            tree.insert(Zone(zone));
+        }
+        tree
+    }
+
+    #[test]
+    fn test_find_longest_suffix() {
+        let tree = tree(&["example.com.", "corp.example.com."]);
+
+        assert_eq!(tree.find("example.com."), Some(&Zone("example.com.")));
+        assert_eq!(tree.find("www.example.com."), Some(&Zone("example.com.")));
+        assert_eq!(tree.find("a.b.corp.example.com."), Some(&Zone("corp.example.com.")));
+        assert_eq!(tree.find("example.org."), None);
+    }
+
+    #[test]
+    fn test_trailing_dot_is_optional() {
+        let tree = tree(&["example.com"]);
+
+        assert_eq!(tree.find("www.example.com."), Some(&Zone("example.com")));
+        assert_eq!(tree.get("example.com."), Some(&Zone("example.com")));
+    }
+
+    #[test]
+    fn test_find_falls_back_past_empty_nodes() {
+        let tree = tree(&["com.", "x.y.com."]);
+
+        assert_eq!(tree.find("z.y.com."), Some(&Zone("com.")));
+        assert_eq!(tree.find("y.com."), Some(&Zone("com.")));
+        assert_eq!(tree.find("a.x.y.com."), Some(&Zone("x.y.com.")));
+    }
+
+    #[test]
+    fn test_wildcard_matches_only_below_apex() {
+        let tree = tree(&["example.", "*.svc.example."]);
+
+        assert_eq!(tree.find("svc.example."), Some(&Zone("example.")));
+        assert_eq!(tree.find("api.svc.example."), Some(&Zone("*.svc.example.")));
+        assert_eq!(tree.find("v1.api.svc.example."), Some(&Zone("*.svc.example.")));
+        assert_eq!(tree.get("svc.example."), None);
+    }
+
+    #[test]
+    fn test_wildcard_precedence() {
+        let tree = tree(&["svc.example.", "*.svc.example.", "db.svc.example."]);
+
+        // The apex value only matches the apex once a wildcard is present
+        assert_eq!(tree.find("svc.example."), Some(&Zone("svc.example.")));
+        assert_eq!(tree.find("api.svc.example."), Some(&Zone("*.svc.example.")));
+        // Exact zones below the wildcard are more specific
+        assert_eq!(tree.find("db.svc.example."), Some(&Zone("db.svc.example.")));
+        assert_eq!(tree.find("a.db.svc.example."), Some(&Zone("db.svc.example.")));
+    }
+
+    #[test]
+    fn test_exclusion() {
+        let mut tree = tree(&["corp.example.", "intranet.public.corp.example."]);
+        tree.exclude("public.corp.example.");
+
+        assert_eq!(tree.find("www.corp.example."), Some(&Zone("corp.example.")));
+        assert_eq!(tree.find("public.corp.example."), None);
+        assert_eq!(tree.find("www.public.corp.example."), None);
+        // Zones inside an exclusion still match
+        assert_eq!(tree.find("a.intranet.public.corp.example."), Some(&Zone("intranet.public.corp.example.")));
+    }
+
+    #[test]
+    fn test_exclusion_with_wildcard() {
+        let mut tree = tree(&["example.", "*.svc.example."]);
+        tree.exclude("svc.example.");
+
+        assert_eq!(tree.find("svc.example."), None);
+        assert_eq!(tree.find("api.svc.example."), Some(&Zone("*.svc.example.")));
+        assert_eq!(tree.find("other.example."), Some(&Zone("example.")));
+    }
+
+    #[test]
+    fn test_insert_replaces() {
+        let mut tree = tree(&["example.com."]);
+
+        assert_eq!(tree.insert(Zone("example.com.")), Some(Zone("example.com.")));
+        assert_eq!(tree.insert(Zone("*.example.com.")), None);
+        assert_eq!(tree.insert(Zone("*.example.com.")), Some(Zone("*.example.com.")));
+    }
+
+    fn paths(tree: &Tree<String, Zone>) -> Vec<String> {
+        let mut paths: Vec<String> = tree.iter().map(|(path, _)| path.join(".")).collect();
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn test_remove_prunes_empty_branches() {
+        let mut tree = tree(&["example.com.", "a.b.example.com.", "*.svc.example.com."]);
+        assert_eq!(tree.len(), 3);
+
+        assert_eq!(tree.remove("a.b.example.com."), Some(Zone("a.b.example.com.")));
+        assert_eq!(tree.remove("a.b.example.com."), None);
+        assert_eq!(tree.find("x.a.b.example.com."), Some(&Zone("example.com.")));
+
+        assert_eq!(tree.remove("*.svc.example.com."), Some(Zone("*.svc.example.com.")));
+        assert_eq!(tree.len(), 1);
+
+        let example = tree.root.children["com"].children.get("example").unwrap();
+        assert!(example.children.is_empty());
+
+        assert_eq!(tree.remove("example.com."), Some(Zone("example.com.")));
+        assert!(tree.is_empty());
+        assert!(tree.root.children.is_empty());
+    }
+
+    #[test]
+    fn test_remove_keeps_exclusions() {
+        let mut tree = tree(&["corp.example.", "www.public.corp.example."]);
+        tree.exclude("public.corp.example.");
+
+        tree.remove("www.public.corp.example.");
+        assert_eq!(tree.find("www.public.corp.example."), None);
+    }
+
+    #[test]
+    fn test_iter_yields_full_paths() {
+        let tree = tree(&["example.com.", "*.example.com.", "a.b.example.com."]);
+
+        assert_eq!(paths(&tree), vec!["*.example.com", "a.b.example.com", "example.com"]);
+        for (path, value) in tree.iter() {
+            assert_eq!(tree.get(path), Some(value));
+        }
+    }
+
+    #[test]
+    fn test_retain() {
+        let mut tree = tree(&["example.com.", "a.example.com.", "*.b.example.com.", "c.d.example.com."]);
+        tree.retain(|zone| zone.0.len() < 15);
+
+        assert_eq!(paths(&tree), vec!["a.example.com", "example.com"]);
+        assert_eq!(tree.len(), 2);
+        assert!(!tree.root.children["com"].children["example"].children.contains_key("d"));
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct LabelZone(&'static str);
+
+    impl TreeSortable<Label> for LabelZone {
+        fn get_path(&self) -> Vec<Label> {
+            self.0.get_path()
+        }
+    }
+
+    fn label_tree(zones: &[&'static str]) -> Tree<Label, LabelZone> {
+        let mut tree = Tree::new();
+        for zone in zones {
+            tree.insert(LabelZone(zone));
+        }
+        tree
+    }
+
+    fn lower(name: &str) -> LowerName {
+        LowerName::from(Name::from_ascii(name).unwrap())
+    }
+
+    #[test]
+    fn test_label_tree_is_case_insensitive() {
+        let tree = label_tree(&["Corp.Example.COM."]);
+
+        assert_eq!(tree.find_name(&lower("host.corp.example.com.")), Some(&LabelZone("Corp.Example.COM.")));
+        assert_eq!(tree.find_name(&lower("HOST.CORP.EXAMPLE.COM.")), Some(&LabelZone("Corp.Example.COM.")));
+        assert_eq!(tree.find(&Name::from_ascii("Host.CORP.example.com.").unwrap()), Some(&LabelZone("Corp.Example.COM.")));
+        assert_eq!(tree.find_name(&lower("example.com.")), None);
+    }
+
+    #[test]
+    fn test_label_tree_escaped_dots() {
+        let tree = label_tree(&["dotted\\.label.example.com."]);
+
+        assert_eq!(tree.find_name(&lower("www.dotted\\.label.example.com.")), Some(&LabelZone("dotted\\.label.example.com.")));
+        assert_eq!(tree.find_name(&lower("www.dotted.label.example.com.")), None);
+        assert_eq!(tree.find_name(&lower("label.example.com.")), None);
+    }
+
+    #[test]
+    fn test_label_tree_trailing_dot_is_optional() {
+        let tree = label_tree(&["example.com"]);
+
+        assert_eq!(tree.find_name(&lower("www.example.com.")), Some(&LabelZone("example.com")));
+        assert_eq!(tree.get("example.com."), Some(&LabelZone("example.com")));
+    }
+
+    #[test]
+    fn test_label_tree_wildcards_and_exclusions() {
+        let mut tree = label_tree(&["corp.example.", "*.svc.example."]);
+        tree.exclude("public.corp.example.");
+
+        assert_eq!(tree.find_name(&lower("a.svc.example.")), Some(&LabelZone("*.svc.example.")));
+        assert_eq!(tree.find_name(&lower("svc.example.")), None);
+        assert_eq!(tree.find_name(&lower("www.corp.example.")), Some(&LabelZone("corp.example.")));
+        assert_eq!(tree.find_name(&lower("www.public.corp.example.")), None);
+    }
+
+    #[test]
+    fn test_find_all_matches_root_to_leaf() {
+        let mut tree = label_tree(&["example.", "corp.example.", "*.corp.example.", "db.corp.example."]);
+        tree.exclude("public.example.");
+
+        let find_all = |name: &str| -> Vec<(usize, &'static str)> {
+            tree.find_all(name).into_iter().map(|(depth, zone)| (depth, zone.0)).collect()
+        };
+
+        assert_eq!(find_all("corp.example."), vec![(1, "example."), (2, "corp.example.")]);
+        assert_eq!(find_all("a.b.corp.example."), vec![(1, "example."), (2, "corp.example."), (3, "*.corp.example.")]);
+        assert_eq!(find_all("x.db.corp.example."), vec![
+            (1, "example."), (2, "corp.example."), (3, "*.corp.example."), (3, "db.corp.example."),
+        ]);
+        assert_eq!(find_all("www.public.example."), vec![]);
+        assert_eq!(find_all("org."), vec![]);
+
+        for name in ["corp.example.", "a.b.corp.example.", "x.db.corp.example.", "www.public.example."] {
+            assert_eq!(tree.find_all(name).last().map(|(_, zone)| *zone), tree.find(name));
+        }
+    }
+
+    #[test]
+    fn test_ancestors() {
+        let tree = label_tree(&["example.", "corp.example.", "*.svc.corp.example."]);
+
+        let ancestors = |name: &str| -> Vec<&'static str> {
+            tree.ancestors(name).into_iter().map(|(_, zone)| zone.0).collect()
+        };
+
+        assert_eq!(ancestors("corp.example."), vec!["example."]);
+        assert_eq!(ancestors("a.corp.example."), vec!["example.", "corp.example."]);
+        assert_eq!(ancestors("*.svc.corp.example."), vec!["example.", "corp.example."]);
+        assert_eq!(ancestors("example."), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = label_tree(&["example.com.", "a.example.com.", "*.b.example.com."]);
+        let new = label_tree(&["EXAMPLE.com", "*.b.example.com.", "c.example.com."]);
+
+        let diff = old.diff(&new);
+        let zones = |values: &[(Vec<Label>, &LabelZone)]| values.iter().map(|(_, zone)| zone.0).collect::<Vec<_>>();
+
+        assert_eq!(zones(&diff.added), vec!["c.example.com."]);
+        assert_eq!(zones(&diff.removed), vec!["a.example.com."]);
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!(diff.changed[0].0, vec![Label::new(b"example"), Label::new(b"com")]);
+        assert_eq!(diff.changed[0].1, &LabelZone("example.com."));
+        assert_eq!(diff.changed[0].2, &LabelZone("EXAMPLE.com"));
+
+        assert!(old.diff(&old).is_empty());
+    }
+}
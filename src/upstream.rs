@@ -0,0 +1,662 @@
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use hickory_resolver::{
+    config::{NameServerConfig, Protocol},
+    system_conf,
+};
+
+use hickory_server::{
+    authority::{MessageResponse, MessageResponseBuilder},
+    proto::{
+        op::{Message, MessageType, OpCode, Query, ResponseCode},
+        rr::{RData, Record},
+        serialize::binary::BinDecodable,
+    },
+    server::Request,
+};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpStream, UdpSocket},
+    time::timeout,
+};
+
+/// Largest message sent over UDP; anything larger goes straight to TCP.
+const MAX_UDP_REQUEST: usize = 512;
+const MAX_UDP_RESPONSE: usize = 65535;
+/// EDNS payload size advertised in queries DNX makes itself, as recommended
+/// to avoid IP fragmentation.
+pub const EDNS_PAYLOAD: u16 = 1232;
+
+/// The servers queries are forwarded to: a single server queried over UDP,
+/// a group of servers, or those the host resolves names with.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
+#[serde(untagged)]
+pub enum DnxNameServers {
+    Address(Ipv4Addr),
+    System(DnxSystemNameServers),
+    Group(Vec<DnxNameServer>),
+}
+
+/// The name servers in the host's `/etc/resolv.conf`, or those of its
+/// network adapters on Windows. Written as `"system"`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum DnxSystemNameServers {
+    System,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum DnxProtocol {
+    #[default]
+    Udp,
+    Tcp,
+    /// DNS over TLS (RFC 7858).
+    Tls,
+    /// DNS over HTTPS (RFC 8484).
+    Https,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct DnxNameServer {
+    pub address: IpAddr,
+    /// Defaults to the standard port of the protocol.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub protocol: DnxProtocol,
+    /// The name the server's certificate must be valid for, required with
+    /// TLS and HTTPS.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls_name: Option<String>,
+}
+
+impl DnxNameServer {
+    fn to_config(&self) -> io::Result<NameServerConfig> {
+        let (protocol, port) = match self.protocol {
+            DnxProtocol::Udp => (Protocol::Udp, 53),
+            DnxProtocol::Tcp => (Protocol::Tcp, 53),
+            DnxProtocol::Tls => (Protocol::Tls, 853),
+            DnxProtocol::Https => (Protocol::Https, 443),
+        };
+        if matches!(protocol, Protocol::Tls | Protocol::Https) && self.tls_name.is_none() {
+            let message = format!("name server {} needs a tls_name for {:?}", self.address, self.protocol);
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+        }
+
+        let mut config = NameServerConfig::new((self.address, self.port.unwrap_or(port)).into(), protocol);
+        config.tls_dns_name = self.tls_name.clone();
+        Ok(config)
+    }
+}
+
+impl DnxNameServers {
+    /// The name servers to configure a resolver with. The host's are read
+    /// from its configuration each time.
+    pub fn to_configs(&self) -> io::Result<Vec<NameServerConfig>> {
+        match self {
+            DnxNameServers::Address(address) => Ok(vec![NameServerConfig::new((*address, 53).into(), Protocol::Udp)]),
+            DnxNameServers::System(_) => {
+                let (config, _) = system_conf::read_system_conf().map_err(io::Error::other)?;
+                Ok(config.name_servers().to_vec())
+            }
+            DnxNameServers::Group(servers) if servers.is_empty() => {
+                Err(io::Error::new(io::ErrorKind::InvalidInput, "name server group is empty"))
+            }
+            DnxNameServers::Group(servers) => servers.iter().map(DnxNameServer::to_config).collect(),
+        }
+    }
+}
+
+/// Rebuilds the message of a request, so it can be relayed to an upstream
+/// as is, keeping its ID, flags and every section.
+pub fn to_message(request: &Request) -> Message {
+    let mut message = Message::new();
+    message.set_header(*request.header());
+    message.add_query(request.query().original().clone());
+    message.add_answers(request.answers().iter().cloned());
+    message.add_name_servers(request.name_servers().iter().cloned());
+    message.add_additionals(request.additionals().iter().cloned());
+    // EDNS and signatures are kept as plain records, in the order they must
+    // appear at the end of the additional section
+    if let Some(edns) = request.edns() {
+        message.add_additional(Record::from(edns));
+    }
+    message.add_additionals(request.sig0().iter().cloned());
+    message
+}
+
+type Records<'a> = std::slice::Iter<'a, Record>;
+
+/// A response from an upstream, to be relayed to a client as it was received.
+#[derive(Debug)]
+pub struct DnxRelayedResponse {
+    message: Message,
+    /// The additional section with the EDNS and signature records last, as
+    /// they were on the wire.
+    additionals: Vec<Record>,
+}
+
+impl From<Message> for DnxRelayedResponse {
+    fn from(message: Message) -> Self {
+        let mut additionals = message.additionals().to_vec();
+        if let Some(edns) = message.extensions() {
+            additionals.push(Record::from(edns));
+        }
+        additionals.extend(message.sig0().iter().cloned());
+        DnxRelayedResponse { message, additionals }
+    }
+}
+
+impl DnxRelayedResponse {
+    pub fn message(&self) -> &Message {
+        &self.message
+    }
+
+    /// The response to `request`, with every section of the upstream's,
+    /// including its EDNS and its TSIG or SIG(0) signature. Only the ID is
+    /// taken from `request`. The question is the client's own, so names
+    /// compress as they did upstream and signatures still verify.
+    pub fn to_response<'q>(
+        &self,
+        request: &'q Request,
+    ) -> MessageResponse<'q, '_, Records<'_>, Records<'_>, Records<'_>, Records<'_>> {
+        let mut header = *self.message.header();
+        header.set_id(request.id());
+        MessageResponseBuilder::from_message_request(request).build(
+            header,
+            self.message.answers(),
+            self.message.name_servers(),
+            &[],
+            &self.additionals,
+        )
+    }
+}
+
+/// A recursive query for `query` with a random ID, for DNX to send an
+/// upstream itself.
+pub fn query_message(query: Query) -> Message {
+    let mut message = Message::new();
+    message.set_id(rand::random());
+    message.set_message_type(MessageType::Query);
+    message.set_op_code(OpCode::Query);
+    message.set_recursion_desired(true);
+    message.add_query(query);
+    message
+}
+
+fn timed_out(server: SocketAddr) -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, format!("upstream {server} timed out"))
+}
+
+/// Exchanges `message` with `server` up to `attempts` times, until one gets
+/// a response.
+pub async fn exchange_attempts(server: SocketAddr, message: &Message, limit: Duration, attempts: usize) -> io::Result<Message> {
+    let mut attempts = attempts.max(1);
+    loop {
+        attempts -= 1;
+        match exchange(server, message, limit).await {
+            Err(e) if attempts > 0 => log::debug!("Retrying exchange with {}: {}", server, e),
+            result => return result,
+        }
+    }
+}
+
+/// Sends `message` to `server` and waits up to `limit` for the response. UDP
+/// is used unless the message is too large for it, and the exchange is
+/// retried over TCP if the response is truncated.
+pub async fn exchange(server: SocketAddr, message: &Message, limit: Duration) -> io::Result<Message> {
+    let request = message.to_vec()?;
+
+    if request.len() <= MAX_UDP_REQUEST {
+        let response = timeout(limit, exchange_udp(server, message.id(), &request))
+            .await
+            .map_err(|_| timed_out(server))??;
+        if !response.truncated() {
+            return Ok(response);
+        }
+        log::trace!("Response from {} truncated, retrying over TCP", server);
+    }
+
+    timeout(limit, exchange_tcp(server, &request))
+        .await
+        .map_err(|_| timed_out(server))?
+}
+
+async fn exchange_udp(server: SocketAddr, id: u16, request: &[u8]) -> io::Result<Message> {
+    let bind: SocketAddr = match server {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(bind).await?;
+    socket.connect(server).await?;
+    socket.send(request).await?;
+
+    let mut buffer = vec![0; MAX_UDP_RESPONSE];
+    loop {
+        let len = socket.recv(&mut buffer).await?;
+        match Message::from_bytes(&buffer[..len]) {
+            Ok(response) if response.id() == id => return Ok(response),
+            Ok(response) => log::debug!("Ignoring response with unexpected ID {} from {}", response.id(), server),
+            Err(e) => log::debug!("Ignoring malformed response from {}: {}", server, e),
+        }
+    }
+}
+
+async fn exchange_tcp(server: SocketAddr, request: &[u8]) -> io::Result<Message> {
+    let mut stream = TcpStream::connect(server).await?;
+    write_tcp(&mut stream, request).await?;
+    read_tcp(&mut stream).await
+}
+
+/// Finds where the records of an AXFR or IXFR response end. An AXFR, or an
+/// IXFR answered with the whole zone, ends with the SOA it started with. An
+/// incremental IXFR alternates between the SOAs opening each set of
+/// deletions and additions, and ends with the first SOA again where the next
+/// deletions would start.
+#[derive(Debug, Default)]
+struct TransferTracker {
+    serial: Option<u32>,
+    incremental: Option<bool>,
+    soas: usize,
+    complete: bool,
+}
+
+impl TransferTracker {
+    fn push(&mut self, record: &Record) {
+        let soa_serial = match record.data() {
+            Some(RData::SOA(soa)) => Some(soa.serial()),
+            _ => None,
+        };
+
+        let Some(serial) = self.serial else {
+            self.serial = soa_serial;
+            return;
+        };
+
+        let incremental = *self.incremental.get_or_insert(soa_serial.is_some());
+        let Some(soa_serial) = soa_serial else {
+            return;
+        };
+
+        self.soas += 1;
+        self.complete = !incremental || (self.soas % 2 == 1 && soa_serial == serial);
+    }
+}
+
+/// A zone transfer relayed from an upstream over TCP, read one message at a
+/// time.
+pub struct DnxZoneTransfer {
+    stream: TcpStream,
+    server: SocketAddr,
+    limit: Duration,
+    tracker: TransferTracker,
+    /// The serial the client already has, for IXFR.
+    known_serial: Option<u32>,
+    done: bool,
+}
+
+impl DnxZoneTransfer {
+    /// Sends the AXFR or IXFR `message` to `server`. Each message read must
+    /// arrive within `limit`.
+    pub async fn start(server: SocketAddr, message: &Message, limit: Duration) -> io::Result<Self> {
+        let mut stream = timeout(limit, TcpStream::connect(server))
+            .await
+            .map_err(|_| timed_out(server))??;
+        write_tcp(&mut stream, &message.to_vec()?).await?;
+
+        let known_serial = message.name_servers().iter().find_map(|record| match record.data() {
+            Some(RData::SOA(soa)) => Some(soa.serial()),
+            _ => None,
+        });
+
+        Ok(DnxZoneTransfer {
+            stream,
+            server,
+            limit,
+            tracker: TransferTracker::default(),
+            known_serial,
+            done: false,
+        })
+    }
+
+    /// Reads the next message of the transfer, or `None` once it's complete.
+    /// An error response ends the transfer after being returned.
+    pub async fn next(&mut self) -> io::Result<Option<Message>> {
+        if self.done {
+            return Ok(None);
+        }
+
+        let message = timeout(self.limit, read_tcp(&mut self.stream))
+            .await
+            .map_err(|_| timed_out(self.server))??;
+
+        let first = self.tracker.serial.is_none();
+        message.answers().iter().for_each(|record| self.tracker.push(record));
+
+        // An IXFR for a serial the client already has is answered with just
+        // the current SOA
+        let up_to_date = first && message.answers().len() == 1 && self.tracker.serial == self.known_serial;
+
+        self.done = self.tracker.complete || up_to_date || message.response_code() != ResponseCode::NoError;
+        Ok(Some(message))
+    }
+}
+
+/// Writes a length-prefixed message to a TCP stream.
+pub async fn write_tcp(stream: &mut TcpStream, message: &[u8]) -> io::Result<()> {
+    let len = u16::try_from(message.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "message too large"))?;
+
+    let mut buffer = Vec::with_capacity(message.len() + 2);
+    buffer.extend_from_slice(&len.to_be_bytes());
+    buffer.extend_from_slice(message);
+    stream.write_all(&buffer).await
+}
+
+/// Reads a length-prefixed message from a TCP stream.
+pub async fn read_tcp(stream: &mut TcpStream) -> io::Result<Message> {
+    let len = stream.read_u16().await?;
+    let mut buffer = vec![0; len.into()];
+    stream.read_exact(&mut buffer).await?;
+
+    Message::from_bytes(&buffer).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use hickory_server::{
+        authority::MessageRequest,
+        proto::{
+            op::{Edns, MessageType, OpCode, Query},
+            rr::{
+                dnssec::{
+                    rdata::tsig::{make_tsig_record, message_tbs, TsigAlgorithm, TSIG},
+                    tsig::TSigner,
+                },
+                rdata::SOA,
+                Name,
+                RData,
+                RecordType,
+            },
+            serialize::binary::BinEncoder,
+        },
+        server::Protocol,
+    };
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    fn update(id: u16) -> Message {
+        let mut message = Message::new();
+        message.set_id(id).set_op_code(OpCode::Update);
+        message.add_query(Query::query(Name::from_str("corp.example.").unwrap(), RecordType::SOA));
+        message
+    }
+
+    fn response(request: &Message, truncated: bool) -> Vec<u8> {
+        let mut response = request.clone();
+        response.set_message_type(MessageType::Response).set_truncated(truncated);
+        response.to_vec().unwrap()
+    }
+
+    /// Answers over UDP with a truncated response and a stray one, then over
+    /// TCP with the full response.
+    async fn upstream() -> SocketAddr {
+        let udp = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = udp.local_addr().unwrap();
+        let tcp = TcpListener::bind(addr).await.unwrap();
+
+        tokio::spawn(async move {
+            let mut buffer = vec![0; MAX_UDP_RESPONSE];
+            let (len, src) = udp.recv_from(&mut buffer).await.unwrap();
+            let request = Message::from_bytes(&buffer[..len]).unwrap();
+
+            udp.send_to(&response(&update(request.id().wrapping_add(1)), false), src).await.unwrap();
+            udp.send_to(&response(&request, true), src).await.unwrap();
+        });
+
+        tokio::spawn(async move {
+            let (mut stream, _) = tcp.accept().await.unwrap();
+            let request = read_tcp(&mut stream).await.unwrap();
+            write_tcp(&mut stream, &response(&request, false)).await.unwrap();
+        });
+
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_exchange_attempts_retries_unanswered_requests() {
+        let udp = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server = udp.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buffer = vec![0; MAX_UDP_RESPONSE];
+            // the first two requests go unanswered
+            for _ in 0..2 {
+                udp.recv_from(&mut buffer).await.unwrap();
+            }
+            let (len, src) = udp.recv_from(&mut buffer).await.unwrap();
+            let request = Message::from_bytes(&buffer[..len]).unwrap();
+            udp.send_to(&response(&request, false), src).await.unwrap();
+        });
+
+        let limit = Duration::from_millis(200);
+        assert!(exchange_attempts(server, &update(42), limit, 1).await.is_err());
+        let response = exchange_attempts(server, &update(43), limit, 2).await.unwrap();
+        assert_eq!(response.id(), 43);
+    }
+
+    #[test]
+    fn test_to_message_keeps_every_section() {
+        let mut message = update(7);
+        let record = Record::from_rdata(
+            Name::from_str("host.corp.example.").unwrap(),
+            300,
+            RData::A("10.0.0.5".parse::<std::net::Ipv4Addr>().unwrap().into()),
+        );
+        message.add_name_server(record.clone());
+        message.set_edns(Edns::new());
+        let bytes = message.to_vec().unwrap();
+
+        let request = MessageRequest::from_bytes(&bytes).unwrap();
+        let request = Request::new(request, "127.0.0.1:5353".parse().unwrap(), Protocol::Udp);
+        let relayed = to_message(&request);
+
+        assert_eq!(relayed.id(), 7);
+        assert_eq!(relayed.op_code(), OpCode::Update);
+        assert_eq!(relayed.queries(), message.queries());
+        assert_eq!(relayed.name_servers(), &[record]);
+        assert_eq!(Message::from_vec(&relayed.to_vec().unwrap()).unwrap().extensions(), message.extensions());
+    }
+
+    #[test]
+    fn test_relayed_update_keeps_tsig_signatures() {
+        let signer = TSigner::new(b"secret".to_vec(), TsigAlgorithm::HmacSha256, Name::from_str("dnx-key.").unwrap(), 300).unwrap();
+        let now = 1_700_000_000;
+
+        let mut message = update(9);
+        message.add_name_server(a("host.corp.example."));
+        message.finalize(&signer, now).unwrap();
+        let bytes = message.to_vec().unwrap();
+        let (request_mac, _, _) = signer.verify_message_byte(None, &bytes, true).unwrap();
+
+        // the update goes upstream as the client signed it
+        let request = MessageRequest::from_bytes(&bytes).unwrap();
+        let request = Request::new(request, "127.0.0.1:5353".parse().unwrap(), Protocol::Udp);
+        let relayed = to_message(&request).to_vec().unwrap();
+        assert!(signer.verify_message_byte(None, &relayed, true).is_ok());
+
+        // and the upstream's signed response comes back as it was sent
+        let mut response = update(9);
+        response.set_message_type(MessageType::Response).set_edns(Edns::new());
+        let pre_tsig = TSIG::new(TsigAlgorithm::HmacSha256, now as u64, 300, Vec::new(), 9, 0, Vec::new());
+        let tbs = message_tbs(Some(&request_mac), &response, &pre_tsig, signer.signer_name()).unwrap();
+        let tsig = pre_tsig.set_mac(signer.sign(&tbs).unwrap());
+        response.add_tsig(make_tsig_record(signer.signer_name().clone(), tsig));
+        let response = Message::from_vec(&response.to_vec().unwrap()).unwrap();
+
+        let mut bytes = Vec::new();
+        DnxRelayedResponse::from(response)
+            .to_response(&request)
+            .destructive_emit(&mut BinEncoder::new(&mut bytes))
+            .unwrap();
+        assert!(signer.verify_message_byte(Some(&request_mac), &bytes, true).is_ok());
+        assert!(Message::from_vec(&bytes).unwrap().extensions().is_some());
+    }
+
+    #[test]
+    fn test_query_message_carries_client_subnet() {
+        let subnet = "192.0.2.0/24".parse().unwrap();
+        let mut message = query_message(Query::query(Name::from_str("cdn.example.").unwrap(), RecordType::A));
+        let mut edns = Edns::new();
+        edns.set_max_payload(EDNS_PAYLOAD);
+        edns.options_mut().insert(crate::ecs::to_option(subnet));
+        message.set_edns(edns);
+
+        let message = Message::from_vec(&message.to_vec().unwrap()).unwrap();
+        assert!(message.recursion_desired());
+        assert_eq!(message.message_type(), MessageType::Query);
+        assert_eq!(message.extensions().as_ref().map(Edns::max_payload), Some(EDNS_PAYLOAD));
+        assert_eq!(crate::ecs::client_subnet(message.extensions().as_ref()), Some(subnet));
+    }
+
+    #[tokio::test]
+    async fn test_exchange_retries_truncated_over_tcp() {
+        let server = upstream().await;
+
+        let response = exchange(server, &update(42), Duration::from_secs(5)).await.unwrap();
+
+        assert_eq!(response.id(), 42);
+        assert_eq!(response.op_code(), OpCode::Update);
+        assert!(!response.truncated());
+    }
+
+    fn soa(serial: u32) -> Record {
+        let soa = SOA::new(
+            Name::from_str("ns1.corp.example.").unwrap(),
+            Name::from_str("hostmaster.corp.example.").unwrap(),
+            serial, 3600, 600, 86400, 60,
+        );
+        Record::from_rdata(Name::from_str("corp.example.").unwrap(), 300, RData::SOA(soa))
+    }
+
+    fn a(name: &str) -> Record {
+        let ip = "10.0.0.5".parse::<std::net::Ipv4Addr>().unwrap();
+        Record::from_rdata(Name::from_str(name).unwrap(), 300, RData::A(ip.into()))
+    }
+
+    fn complete_after(records: &[Record]) -> Vec<bool> {
+        let mut tracker = TransferTracker::default();
+        records.iter().map(|record| {
+            tracker.push(record);
+            tracker.complete
+        }).collect()
+    }
+
+    #[test]
+    fn test_transfer_tracker_axfr() {
+        let records = [soa(5), a("a.corp.example."), soa(5)];
+        assert_eq!(complete_after(&records), vec![false, false, true]);
+
+        assert_eq!(complete_after(&[soa(5), soa(5)]), vec![false, true]);
+    }
+
+    #[test]
+    fn test_transfer_tracker_ixfr() {
+        let records = [
+            soa(7),
+            soa(5), a("old.corp.example."), soa(6), a("new.corp.example."),
+            soa(6), soa(7), a("newer.corp.example."),
+            soa(7),
+        ];
+        assert_eq!(complete_after(&records), vec![false, false, false, false, false, false, false, false, true]);
+    }
+
+    /// Streams an AXFR response split over two messages.
+    async fn transfer_upstream() -> SocketAddr {
+        let tcp = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = tcp.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let (mut stream, _) = tcp.accept().await.unwrap();
+            let request = read_tcp(&mut stream).await.unwrap();
+
+            for records in [vec![soa(5), a("a.corp.example.")], vec![a("b.corp.example."), soa(5)]] {
+                let mut response = request.clone();
+                response.set_message_type(MessageType::Response);
+                response.add_answers(records);
+                write_tcp(&mut stream, &response.to_vec().unwrap()).await.unwrap();
+            }
+        });
+
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_zone_transfer_reads_until_closing_soa() {
+        let server = transfer_upstream().await;
+        let mut request = Message::new();
+        request.set_id(9);
+        request.add_query(Query::query(Name::from_str("corp.example.").unwrap(), RecordType::AXFR));
+
+        let mut transfer = DnxZoneTransfer::start(server, &request, Duration::from_secs(5)).await.unwrap();
+        let mut records = 0;
+        while let Some(message) = transfer.next().await.unwrap() {
+            assert_eq!(message.id(), 9);
+            records += message.answers().len();
+        }
+
+        assert_eq!(records, 4);
+    }
+
+    #[tokio::test]
+    async fn test_exchange_times_out() {
+        let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server = silent.local_addr().unwrap();
+
+        let error = exchange(server, &update(1), Duration::from_millis(50)).await.unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn test_name_servers_config() {
+        use hickory_resolver::config::Protocol;
+
+        let parse = |json: &str| serde_json::from_str::<DnxNameServers>(json).unwrap();
+
+        let address = parse(r#""1.1.1.1""#);
+        assert_eq!(address, DnxNameServers::Address(Ipv4Addr::new(1, 1, 1, 1)));
+        assert_eq!(parse(r#""system""#), DnxNameServers::System(DnxSystemNameServers::System));
+        assert!(serde_json::from_str::<DnxNameServers>(r#""elsewhere""#).is_err());
+
+        let configs = address.to_configs().unwrap();
+        assert_eq!(configs.len(), 1);
+        assert_eq!(configs[0].socket_addr, "1.1.1.1:53".parse().unwrap());
+        assert_eq!(configs[0].protocol, Protocol::Udp);
+
+        let group = parse(r#"[
+            { "address": "9.9.9.9" },
+            { "address": "2620:fe::fe", "protocol": "tls", "tls_name": "dns.quad9.net" },
+            { "address": "1.1.1.1", "port": 8443, "protocol": "https", "tls_name": "cloudflare-dns.com" }
+        ]"#);
+        let configs = group.to_configs().unwrap();
+        let servers: Vec<_> = configs.iter().map(|config| (config.socket_addr, config.protocol)).collect();
+        assert_eq!(servers, [
+            ("9.9.9.9:53".parse().unwrap(), Protocol::Udp),
+            ("[2620:fe::fe]:853".parse().unwrap(), Protocol::Tls),
+            ("1.1.1.1:8443".parse().unwrap(), Protocol::Https),
+        ]);
+        assert_eq!(configs[1].tls_dns_name.as_deref(), Some("dns.quad9.net"));
+
+        assert!(parse(r#"[{ "address": "9.9.9.9", "protocol": "tls" }]"#).to_configs().is_err());
+        assert!(parse("[]").to_configs().is_err());
+    }
+}
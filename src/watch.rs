@@ -0,0 +1,68 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+/// Tracks a file's modification time so it can be reloaded when it changes.
+#[derive(Debug)]
+pub struct DnxFileWatch {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+impl DnxFileWatch {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        let path = path.as_ref().to_path_buf();
+        let modified = modified(&path);
+
+        DnxFileWatch { path, modified }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns true if the file was modified, created or removed since the
+    /// last call.
+    pub fn changed(&mut self) -> bool {
+        let modified = modified(&self.path);
+        if modified == self.modified {
+            return false;
+        }
+
+        self.modified = modified;
+        true
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_file_watch_detects_changes() {
+        let path = std::env::temp_dir().join(format!("dnx-watch-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut watch = DnxFileWatch::new(&path);
+        assert!(!watch.changed());
+
+        fs::write(&path, "one").unwrap();
+        assert!(watch.changed());
+        assert!(!watch.changed());
+
+        let file = fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(60)).unwrap();
+        assert!(watch.changed());
+
+        fs::remove_file(&path).unwrap();
+        assert!(watch.changed());
+    }
+}